@@ -0,0 +1,52 @@
+use std::cmp::Ordering;
+use crate::prometheus::{AlertState, Labels, RuleType, RulesData};
+use crate::timespec::parse_rfc3339;
+
+// A pending or firing alert, flattened out of its rule for the alerts view.
+pub struct ActiveAlert {
+    pub server: String,
+    pub name: String,
+    pub state: AlertState,
+    pub labels: Labels,
+    pub annotations: Labels,
+    pub expr: String,
+    // when the alert became pending
+    pub active_since: Option<f64>,
+    // when it went on to fire, once its rule's `for` had passed
+    pub firing_since: Option<f64>,
+    pub value: String,
+}
+
+pub fn active_alerts(server: &str, rules: RulesData) -> Vec<ActiveAlert> {
+    let mut alerts: Vec<ActiveAlert> = rules.groups.into_iter()
+        .flat_map(|g| g.rules)
+        .filter(|r| r.kind == RuleType::Alerting)
+        .flat_map(|rule| {
+            let server = server.to_string();
+            rule.alerts.into_iter()
+                .filter(|a| a.state != AlertState::Inactive)
+                .map(move |a| {
+                    let active_since = a.active_at.as_deref().and_then(parse_rfc3339);
+                    ActiveAlert {
+                        server: server.clone(),
+                        name: rule.name.clone(),
+                        state: a.state,
+                        labels: a.labels,
+                        annotations: a.annotations,
+                        expr: rule.query.clone(),
+                        active_since,
+                        firing_since: active_since.filter(|_| a.state == AlertState::Firing).map(|t| t + rule.duration),
+                        value: a.value,
+                    }
+                })
+        })
+        .collect();
+    alerts.sort_by(alert_order);
+    alerts
+}
+
+// firing alerts first, then pending, oldest first within each
+pub fn alert_order(a: &ActiveAlert, b: &ActiveAlert) -> Ordering {
+    a.state.cmp(&b.state)
+        .then(a.active_since.unwrap_or(f64::INFINITY).total_cmp(&b.active_since.unwrap_or(f64::INFINITY)))
+}
@@ -1,181 +1,1027 @@
 use std::borrow::Cow;
-use std::collections::{BTreeMap, VecDeque};
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fs::OpenOptions;
+use std::io::Write;
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
 use crate::consts::*;
 use tui_menu::{MenuItem, MenuState};
-use crate::cfg_file::{ConfigFile, QueryRef, ServerRef};
-use crate::cli::Query;
-use anyhow::bail;
-use handlebars::Handlebars;
-use reqwest::Error as reqwestError;
-use crate::prometheus::PromResponse;
-use prometheus_http_query::{Error, Selector, RuleType};
-use prometheus_http_query::Client as phqc;
+use crate::cfg_file::{ConfigFile, PanelKind};
+use crate::alerts::{active_alerts, alert_order, ActiveAlert};
+use crate::check::validate;
+use crate::client::{ApiResult, ClientError, PromClient};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use crate::completion::{self, CacheEntry, CacheKey, CompletionCache, Completions, Context, Request};
+use crate::editor::{Editor, EditorMode};
+use crate::heatmap::Heatmap;
+use crate::panel::{InFlight, Panel, QuerySource, QueryTarget};
+use crate::prometheus::{QueryData, RulesData};
+use crate::timespec::{auto_step, format_tick, now_secs};
+use crate::variables::{parse_label_values, Variable};
+
+pub struct FetchResult {
+    pub panel: usize,
+    pub id: u64,
+    pub window: (f64, f64),
+    pub outcome: Result<ApiResult<QueryData>, ClientError>,
+}
+
+pub struct CompletionResult {
+    pub server: String,
+    pub key: CacheKey,
+    pub outcome: Result<CacheEntry, ClientError>,
+}
+
+pub struct AlertsResult {
+    pub server: String,
+    pub outcome: Result<ApiResult<RulesData>, ClientError>,
+}
+
+pub struct VariableResult {
+    pub generation: u64,
+    pub var: usize,
+    pub outcome: Result<ApiResult<Vec<String>>, ClientError>,
+}
+
+// which variable's dropdown is open and the highlighted row in it
+pub struct Picker {
+    pub var: usize,
+    pub row: usize,
+}
 
 pub struct AppData {
     pub config: ConfigFile,
+    pub panels: Vec<Panel>,
+    pub focused: usize,
+    pub dashboard: Option<String>,
+    pub columns: u16,
+    // -e template variables, overridden by dashboard variables once they resolve
+    pub vars: BTreeMap<String, String>,
+    pub variables: Vec<Variable>,
+    pub picker: Option<Picker>,
+    // text of the query input bar, edited through `editor` while it is open
     pub query: String,
-    pub data: Vec<(f64, f64)>,
+    pub editor: Option<Editor>,
+    pub completion_cache: CompletionCache,
+    // one client per server name, so requests reuse connections; cleared when the config is reloaded
+    clients: HashMap<String, PromClient>,
+    // pending and firing alerts for each panel server, or why they couldn't be fetched
+    pub alerts: BTreeMap<String, Result<Vec<ActiveAlert>, String>>,
+    // the highlighted row while the alerts view is open
+    pub alerts_view: Option<usize>,
+    pub range_secs: f64,
+    // None follows "now"; Some pins the right edge of the window after panning
+    pub range_end: Option<f64>,
+    pub refresh_requested: bool,
+    // crosshair timestamp, snapped to a sample
+    pub cursor: Option<f64>,
+    pub menu_active: bool,
     pub tick_interval_msecs: u64,
     pub log_buffer: Arc<Mutex<VecDeque<String>>>,
     pub menu: MenuState<Cow<'static, str>>,
     pub last_request: Instant,
     pub last_refresh: Instant,
+    // set by the reload key, the menu or SIGHUP; picked up on the next tick
+    pub reload_requested: Arc<AtomicBool>,
+    config_stamps: Vec<Option<SystemTime>>,
+    last_config_poll: Instant,
+    next_request_id: u64,
+    // bumped whenever the panel set changes so late variable lookups can be discarded
+    generation: u64,
+    fetch_tx: UnboundedSender<FetchResult>,
+    fetch_rx: UnboundedReceiver<FetchResult>,
+    var_tx: UnboundedSender<VariableResult>,
+    var_rx: UnboundedReceiver<VariableResult>,
+    completion_tx: UnboundedSender<CompletionResult>,
+    completion_rx: UnboundedReceiver<CompletionResult>,
+    alerts_in_flight: HashSet<String>,
+    last_alerts_poll: Instant,
+    alerts_tx: UnboundedSender<AlertsResult>,
+    alerts_rx: UnboundedReceiver<AlertsResult>,
 }
 
 impl AppData {
-    pub fn new(config_path: Option<PathBuf>) -> AppData {
+    pub fn new(config: ConfigFile) -> AppData {
+        let (fetch_tx, fetch_rx) = unbounded_channel();
+        let (var_tx, var_rx) = unbounded_channel();
+        let (completion_tx, completion_rx) = unbounded_channel();
+        let (alerts_tx, alerts_rx) = unbounded_channel();
         AppData {
-            config: ConfigFile::new(config_path).unwrap(),
+            config_stamps: config.search().stamps(),
+            config,
+            panels: vec![],
+            focused: 0,
+            dashboard: None,
+            columns: 1,
+            vars: BTreeMap::new(),
+            variables: vec![],
+            picker: None,
             query: "".to_string(),
-            data: vec![],
+            editor: None,
+            completion_cache: CompletionCache::default(),
+            clients: HashMap::new(),
+            alerts: BTreeMap::new(),
+            alerts_view: None,
+            range_secs: DEFAULT_TUI_RANGE_SECS,
+            range_end: None,
+            refresh_requested: true,
+            cursor: None,
+            menu_active: false,
             tick_interval_msecs: DEFAULT_TICK_INTERVAL_MSECS,
             log_buffer: Arc::new(Mutex::new(VecDeque::with_capacity(DEFAULT_LOG_RING_BUFFER_SIZE))),
             menu: make_menu(),
             last_request: Instant::now() - Duration::from_secs(20),
             last_refresh: Instant::now() - Duration::from_secs(20),
+            reload_requested: Arc::new(AtomicBool::new(false)),
+            last_config_poll: Instant::now(),
+            next_request_id: 0,
+            generation: 0,
+            fetch_tx,
+            fetch_rx,
+            var_tx,
+            var_rx,
+            completion_tx,
+            completion_rx,
+            alerts_in_flight: HashSet::new(),
+            last_alerts_poll: Instant::now() - Duration::from_secs(ALERTS_REFRESH_SECS),
+            alerts_tx,
+            alerts_rx,
+        }
+    }
+
+    // Points the dashboard at a new server/query, surfacing lookup problems before the first fetch.
+    pub fn set_target(&mut self, target: QueryTarget) {
+        let panel = Panel::new(target.title(), target, &self.config);
+        self.set_panels(None, 1, vec![panel]);
+    }
+
+    // Lays out the named [[dashboards]] entry, rendering every panel with the same template variables.
+    pub fn set_dashboard(&mut self, name: &str, vars: &BTreeMap<String, String>) {
+        let dashboard = match self.config.dashboards.iter().find(|d| d.name == name) {
+            Some(d) => d.clone(),
+            None => {
+                let e = format!("There was no dashboard named '{}'", name);
+                warn!("{}", e);
+                // keep the name so a config reload that adds the dashboard picks it up
+                self.set_panels(Some(name.to_string()), 1, vec![Panel::error(name.to_string(), e)]);
+                return;
+            }
+        };
+        let panels = dashboard.panels.iter()
+            .map(|p| Panel::from_ref(&dashboard, p, vars, &self.config))
+            .collect();
+        self.set_panels(Some(dashboard.name.clone()), dashboard.columns.max(1), panels);
+        self.vars = vars.clone();
+        self.variables = dashboard.variables.iter()
+            .map(|v| {
+                let mut def = v.clone();
+                // -e name=value preselects a dashboard variable
+                if let Some(val) = vars.get(&v.name) {
+                    def.default = Some(val.clone());
+                }
+                Variable::new(def)
+            })
+            .collect();
+        for i in 0..self.variables.len() {
+            self.load_variable(i, dashboard.server.as_deref());
+        }
+    }
+
+    // the server's client, built on first use; None is the default server
+    fn client(&mut self, server: Option<&str>) -> Result<PromClient, ClientError> {
+        let name = server.unwrap_or(DEFAULT_SERVER_NAME);
+        if let Some(client) = self.clients.get(name) {
+            return Ok(client.clone());
+        }
+        let client = PromClient::from_config(&self.config, Some(name))?;
+        self.clients.insert(name.to_string(), client.clone());
+        Ok(client)
+    }
+
+    //region Dashboard Variables
+    fn load_variable(&mut self, var: usize, dashboard_server: Option<&str>) {
+        let def = &self.variables[var].def;
+        let server = def.server.clone().or_else(|| dashboard_server.map(String::from));
+        let (selector, label) = match parse_label_values(&def.query) {
+            Ok(l) => l,
+            Err(e) => {
+                warn!("variable {}: {}", def.name, e);
+                self.variables[var].error = Some(e);
+                return;
+            }
+        };
+        let client = match self.client(server.as_deref()) {
+            Ok(c) => c,
+            Err(e) => {
+                warn!("variable {}: {}", self.variables[var].def.name, e);
+                self.variables[var].error = Some(e.to_string());
+                return;
+            }
+        };
+        let (start, end) = self.current_window();
+        let tx = self.var_tx.clone();
+        let generation = self.generation;
+        tokio::spawn(async move {
+            let timeout = Duration::from_secs(DEFAULT_REQUEST_TIMEOUT_SECS);
+            let outcome = match tokio::time::timeout(timeout, client.label_values(&label, selector.as_deref(), start, end)).await {
+                Ok(r) => r,
+                Err(_) => Err(ClientError::Timeout(timeout))
+            };
+            let _ = tx.send(VariableResult { generation, var, outcome });
+        });
+    }
+
+    fn drain_variables(&mut self) {
+        let mut changed = false;
+        while let Ok(result) = self.var_rx.try_recv() {
+            let variable = match self.variables.get_mut(result.var) {
+                Some(v) if result.generation == self.generation => v,
+                _ => continue
+            };
+            match result.outcome {
+                Ok(r) => variable.set_options(r.data),
+                Err(e) => {
+                    warn!("variable {}: {}", variable.def.name, e);
+                    variable.error = Some(e.to_string());
+                }
+            }
+            changed = true;
+        }
+        if changed {
+            self.apply_variables();
         }
     }
 
-    pub async fn on_tick(&mut self) {
-        let now = Instant::now();
-        if self.last_refresh.elapsed() >= Duration::from_secs(MINIMUM_SERVER_WAIT_SECS) {
-            debug!("in query loop");
-            self.execute_promhttp_query_range("default".to_string(), "cpu".to_string()).await;
-            self.last_refresh = now;
+    pub fn variables_ready(&self) -> bool {
+        self.variables.iter().all(|v| v.is_ready())
+    }
+
+    // re-renders every panel's query with the current selections
+    fn apply_variables(&mut self) {
+        let mut vars = self.vars.clone();
+        for v in self.variables.iter().filter(|v| v.options.is_some()) {
+            vars.insert(v.def.name.clone(), v.value());
         }
+        for p in self.panels.iter_mut() {
+            p.target.vars = vars.clone();
+        }
+        self.refresh_requested = true;
     }
 
-    pub async fn execute_promhttp_query_range(&mut self, servername: String, queryname: String) {
-        let server: Option<&ServerRef> = self.config.servers.iter().find(|s| s.name == servername);
-        if server.is_none() {
-            warn!("Can't find a default server to query, please specify default server in config.toml or specify server via -s");
+    pub fn open_picker(&mut self) {
+        if !self.variables.is_empty() {
+            self.picker = Some(Picker { var: 0, row: 0 });
+        }
+    }
+
+    pub fn picker_move(&mut self, vars: isize, rows: isize) {
+        let count = self.variables.len() as isize;
+        if let Some(p) = self.picker.as_mut() {
+            p.var = (p.var as isize + vars).rem_euclid(count.max(1)) as usize;
+            let choices = self.variables[p.var].choices().len() as isize;
+            p.row = match vars {
+                0 => (p.row as isize + rows).clamp(0, (choices - 1).max(0)) as usize,
+                _ => 0
+            };
+        }
+    }
+
+    pub fn picker_toggle(&mut self) {
+        let (var, row) = match &self.picker {
+            Some(p) => (p.var, p.row),
+            None => return
+        };
+        let choice = match self.variables[var].choices().get(row) {
+            Some(c) => c.clone(),
+            None => return
+        };
+        self.variables[var].toggle(&choice);
+        self.apply_variables();
+    }
+    //endregion
+
+    //region Query Editor
+    // opens the input bar on the focused panel's expression, or the named query it runs
+    pub fn open_editor(&mut self) {
+        self.query = match self.panels.get(self.focused).and_then(|p| p.target.source.as_ref()) {
+            Some(QuerySource::Inline(expr)) => expr.clone(),
+            Some(QuerySource::Named(name)) => self.config.queries.iter()
+                .find(|q| &q.name == name)
+                .map(|q| q.query.trim().to_string())
+                .unwrap_or_default(),
+            None => "".to_string()
+        };
+        self.editor = Some(Editor::new(&self.query));
+    }
+
+    pub fn edit(&mut self, f: impl FnOnce(&mut Editor, &mut String)) {
+        if let Some(editor) = self.editor.as_mut() {
+            f(editor, &mut self.query);
+            if editor.completion.is_some() {
+                self.refresh_completion();
+            }
+        }
+    }
+
+    pub fn editor_enter(&mut self) {
+        let mode = match self.editor.as_mut() {
+            Some(e) => std::mem::replace(&mut e.mode, EditorMode::Edit),
+            None => return
+        };
+        match mode {
+            EditorMode::Edit => self.run_editor_query(),
+            EditorMode::Search { term, hit } => {
+                if let Some(editor) = self.editor.as_mut() {
+                    editor.mode = EditorMode::Search { term, hit };
+                    editor.accept_search(&mut self.query);
+                }
+            }
+            EditorMode::Save { name } => {
+                if let Err(e) = self.save_editor_query(name.trim()) {
+                    warn!("Unable to save query: {}", e);
+                }
+            }
+        }
+    }
+
+    // leaves search or save mode, or closes the bar when already editing
+    pub fn editor_escape(&mut self) {
+        match self.editor.as_mut() {
+            Some(e) if !matches!(e.mode, EditorMode::Edit) => e.mode = EditorMode::Edit,
+            _ => self.editor = None
+        }
+    }
+
+    pub fn editor_save(&mut self) {
+        if let Some(e) = self.editor.as_mut() {
+            e.mode = EditorMode::Save { name: String::new() };
+        }
+    }
+
+    // runs the expression in the focused panel, or in a new one when nothing is open
+    fn run_editor_query(&mut self) {
+        let expr = self.query.trim().to_string();
+        if expr.is_empty() {
             return;
         }
-        let query: Option<&QueryRef> = self.config.queries.iter().find(|q| q.name == queryname);
+        if let Some(editor) = self.editor.as_mut() {
+            editor.record(&expr);
+        }
+        self.editor = None;
+        if self.panels.is_empty() {
+            let target = QueryTarget {
+                server: DEFAULT_SERVER_NAME.to_string(),
+                source: Some(QuerySource::Inline(expr)),
+                vars: self.vars.clone(),
+                legend: None,
+                unit: None,
+                decimals: None,
+            };
+            self.set_target(target);
+            return;
+        }
+        self.cancel_fetch(self.focused);
+        let panel = &mut self.panels[self.focused];
+        panel.target.source = Some(QuerySource::Inline(expr));
+        panel.title = panel.target.title();
+        panel.error = panel.target.validate(&self.config).err();
+        panel.data.clear();
+        self.refresh_requested = true;
+    }
+
+    // appends the expression to the most specific config file as a new [[queries]] entry
+    fn save_editor_query(&mut self, name: &str) -> Result<(), String> {
+        let expr = self.query.trim();
+        if name.is_empty() {
+            return Err("a name is required".to_string());
+        }
+        if expr.is_empty() {
+            return Err("the expression is empty".to_string());
+        }
+        if self.config.queries.iter().any(|q| q.name == name) {
+            return Err(format!("a query named '{}' already exists", name));
+        }
+        let path = match self.config.sources.last() {
+            Some(p) => p.clone(),
+            None => return Err("no config file is loaded".to_string())
+        };
+        let entry = format!("\n[[queries]]\nname = {}\nquery = {}\n",
+                            toml::Value::String(name.to_string()),
+                            toml::Value::String(expr.to_string()));
+        let mut file = OpenOptions::new().append(true).open(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        file.write_all(entry.as_bytes()).map_err(|e| format!("{}: {}", path.display(), e))?;
+        info!("Saved query '{}' to {}", name, path.display());
+        self.request_reload();
+        Ok(())
+    }
+    //endregion
+
+    //region Completion
+    // metadata comes from the focused panel's server
+    fn completion_server(&self) -> String {
+        self.panels.get(self.focused)
+            .map(|p| p.target.server.clone())
+            .unwrap_or_else(|| DEFAULT_SERVER_NAME.to_string())
+    }
 
-        let query_string = match query {
-            Some(e) => &e.query,
+    // Tab: opens the popup, or completes straight away when there is only one candidate
+    pub fn editor_complete(&mut self) {
+        let cursor = match &self.editor {
+            Some(e) if matches!(e.mode, EditorMode::Edit) => e.cursor,
+            _ => return
+        };
+        let request = match completion::request(&self.query[..cursor]) {
+            Some(r) => r,
+            None => return
+        };
+        self.show_completions(&request, cursor, 0);
+        if let Some(Completions { items, loading: false, .. }) = self.editor.as_ref().and_then(|e| e.completion.as_ref()) {
+            if items.len() == 1 {
+                self.accept_completion();
+            }
+        }
+    }
+
+    // re-filters the open popup after an edit or when metadata arrives
+    fn refresh_completion(&mut self) {
+        let (cursor, selected) = match &self.editor {
+            Some(Editor { cursor, completion: Some(c), .. }) => (*cursor, c.selected),
+            _ => return
+        };
+        let request = match completion::request(&self.query[..cursor]) {
+            Some(r) => r,
             None => {
-                warn!("There was no query by that name.");
+                self.close_completion();
                 return;
             }
         };
-        let query_start = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() - 3600;
-        let query_end = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        self.show_completions(&request, cursor, selected);
+    }
+
+    // fills the popup from the cache, fetching whatever the context needs that isn't there yet
+    fn show_completions(&mut self, request: &completion::Request, cursor: usize, selected: usize) {
+        let server = self.completion_server();
+        for key in CompletionCache::keys(&request.context) {
+            if !self.completion_cache.contains(&server, &key) {
+                self.fetch_completion(&server, key);
+            }
+        }
+        let (items, loading) = self.completion_cache.candidates(&server, &request.context, &self.query[request.start..cursor]);
+        let error = self.completion_cache.error(&server, &request.context).map(String::from);
+        if let Some(editor) = self.editor.as_mut() {
+            let selected = selected.min(items.len().saturating_sub(1));
+            editor.completion = Some(Completions { items, selected, start: request.start, loading, error });
+        }
+    }
+
+    pub fn completing(&self) -> bool {
+        matches!(&self.editor, Some(Editor { completion: Some(_), .. }))
+    }
+
+    // punctuation ends the word being completed, except in a label value, which only its closing quote ends
+    pub fn ends_completion(&self, c: char) -> bool {
+        let before = match &self.editor {
+            Some(e) => &self.query[..e.cursor],
+            None => return true
+        };
+        match completion::request(before) {
+            Some(Request { context: Context::LabelValue { .. }, start }) => before[..start].ends_with(c),
+            _ => !completion::is_word(c)
+        }
+    }
 
-        let handlebars = Handlebars::new();
+    pub fn close_completion(&mut self) {
+        if let Some(editor) = self.editor.as_mut() {
+            editor.completion = None;
+        }
+    }
 
-        let mut data = BTreeMap::new();
-        //for e in self.args.eval.clone() {
-        //    let val: Vec<String> = e.split("=").map(str::to_string).collect();
-        // TODO: un-debug this
-        data.insert("podex".to_string(), ".+".to_string());
-        //}
-        let interp_string = handlebars.render_template(&query_string, &data).unwrap();
-        let client: phqc = {
-            let c= reqwest::Client::builder()
-                .build().unwrap();
-            phqc::from(c, &server.unwrap().url).unwrap()
+    pub fn completion_move(&mut self, delta: isize) {
+        if let Some(c) = self.editor.as_mut().and_then(|e| e.completion.as_mut()) {
+            let count = c.items.len() as isize;
+            c.selected = (c.selected as isize + delta).rem_euclid(count.max(1)) as usize;
+        }
+    }
+
+    // replaces the word being completed with the highlighted candidate
+    pub fn accept_completion(&mut self) {
+        let editor = match self.editor.as_mut() {
+            Some(e) => e,
+            None => return
+        };
+        let popup = match editor.completion.take() {
+            Some(c) => c,
+            None => return
         };
-        let response = match client.query_range(interp_string, query_start as i64, query_end as i64, 60 as f64).get().await {
-            Ok(e) => e,
+        if let Some(item) = popup.items.get(popup.selected) {
+            let insertion = item.insertion();
+            self.query.replace_range(popup.start..editor.cursor, &insertion);
+            editor.cursor = popup.start + insertion.len();
+        }
+    }
+
+    fn fetch_completion(&mut self, server: &str, key: CacheKey) {
+        let client = match self.client(Some(server)) {
+            Ok(c) => c,
             Err(e) => {
-                warn!("promhttplib is angy: {}", e);
+                self.completion_cache.insert(server, key, CacheEntry::Failed(e.to_string()));
                 return;
             }
         };
-        response.data().as_matrix().iter().for_each(|rvvec| {
-            // rv is a vec of RangeVectors
-            rvvec.iter().for_each(|rv| {
-                rv.samples().iter().for_each(|sample| {
-                    self.data.push((sample.timestamp(), sample.value()));
-                })
-
-            })
+        self.completion_cache.insert(server, key.clone(), CacheEntry::Loading);
+        let (start, end) = self.current_window();
+        let tx = self.completion_tx.clone();
+        let server = server.to_string();
+        tokio::spawn(async move {
+            let timeout = Duration::from_secs(DEFAULT_REQUEST_TIMEOUT_SECS);
+            let request = async {
+                match &key {
+                    CacheKey::Metrics => client.label_values("__name__", None, start, end).await.map(|r| CacheEntry::Names(r.data)),
+                    CacheKey::Metadata => client.metadata().await.map(|r| CacheEntry::Metadata(r.data)),
+                    CacheKey::Labels(metric) => client.label_names(metric.as_deref(), start, end).await.map(|r| CacheEntry::Names(r.data)),
+                    CacheKey::Values(metric, label) => {
+                        client.label_values(label, metric.as_deref(), start, end).await.map(|r| CacheEntry::Names(r.data))
+                    }
+                }
+            };
+            let outcome = match tokio::time::timeout(timeout, request).await {
+                Ok(r) => r,
+                Err(_) => Err(ClientError::Timeout(timeout))
+            };
+            let _ = tx.send(CompletionResult { server, key, outcome });
         });
     }
 
-    pub fn execute_query_range(&mut self, servername: String, queryname: String) -> anyhow::Result<Vec<(f64, f64)>> {
-        let server: Option<&ServerRef> = self.config.servers.iter().find(|s| s.name == servername);
-        if server.is_none() {
-            anyhow::bail!("Can't find a default server to query, please specify default server in config.toml or specify server via -s");
+    fn drain_completions(&mut self) {
+        let mut changed = false;
+        while let Ok(result) = self.completion_rx.try_recv() {
+            let entry = match result.outcome {
+                Ok(e) => e,
+                Err(e) => {
+                    warn!("completion: {}", e);
+                    CacheEntry::Failed(e.to_string())
+                }
+            };
+            self.completion_cache.insert(&result.server, result.key, entry);
+            changed = true;
+        }
+        if changed {
+            self.refresh_completion();
         }
+    }
+    //endregion
 
-        let query: Option<&QueryRef> = self.config.queries.iter().find(|q| q.name == queryname);
+    //region Alerts
+    // the servers behind the panels on screen, which alerts are fetched from
+    fn alert_servers(&self) -> Vec<String> {
+        let mut servers: Vec<String> = self.panels.iter().map(|p| p.target.server.clone()).collect();
+        if servers.is_empty() {
+            servers.push(DEFAULT_SERVER_NAME.to_string());
+        }
+        servers.sort();
+        servers.dedup();
+        servers
+    }
 
-        let query_string = match query {
-            Some(e) => &e.query,
+    pub fn toggle_alerts(&mut self) {
+        self.alerts_view = match self.alerts_view {
+            Some(_) => None,
             None => {
-                bail!("There was no query by that name.")
-            }
-        };
-        let query_start = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() - 3600;
-        let query_end = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-        let full_url: String = format!("{}/api/v1/query_range", server.unwrap().url);
-
-        let handlebars = Handlebars::new();
-
-        let mut data = BTreeMap::new();
-        //for e in self.args.eval.clone() {
-        //    let val: Vec<String> = e.split("=").map(str::to_string).collect();
-        // TODO: un-debug this
-        data.insert("podex".to_string(), ".+".to_string());
-        //}
-        let interp_string = handlebars.render_template(&query_string, &data)?;
-        let rs = reqwest::blocking::Client::new()
-            .post(&full_url)
-            .form(&[
-                ("query", interp_string),
-                ("step", "1m".to_string()),
-                ("start", query_start.to_string()),
-                ("end", query_end.to_string())
-            ])
-            .send()?;
-        let result = match rs.text() {
-            Ok(s) => {
-                debug!("{:#?}", s);
-                s
-            }
-            Err(e) => bail!("no text from response: {}", e)
-        };
-        let rsjson: PromResponse = match serde_json::from_str::<PromResponse>(&result) {
+                self.fetch_alerts();
+                Some(0)
+            }
+        };
+    }
+
+    pub fn alerts_move(&mut self, delta: isize) {
+        let last = self.active_alerts().len().saturating_sub(1) as isize;
+        if let Some(row) = self.alerts_view.as_mut() {
+            *row = (*row as isize + delta).clamp(0, last) as usize;
+        }
+    }
+
+    // every server's alerts in one list
+    pub fn active_alerts(&self) -> Vec<&ActiveAlert> {
+        let mut alerts: Vec<&ActiveAlert> = self.alerts.values().flat_map(|r| r.iter().flatten()).collect();
+        alerts.sort_by(|a, b| alert_order(a, b));
+        alerts
+    }
+
+    // when each of the server's firing alerts started firing, for markers on its graphs
+    pub fn alert_markers(&self, server: &str) -> Vec<f64> {
+        match self.alerts.get(server) {
+            Some(Ok(alerts)) => alerts.iter().filter_map(|a| a.firing_since).collect(),
+            _ => vec![]
+        }
+    }
+
+    fn poll_alerts(&mut self) {
+        if self.last_alerts_poll.elapsed() >= Duration::from_secs(ALERTS_REFRESH_SECS) {
+            self.fetch_alerts();
+        }
+    }
+
+    fn fetch_alerts(&mut self) {
+        self.last_alerts_poll = Instant::now();
+        let servers = self.alert_servers();
+        self.alerts.retain(|s, _| servers.contains(s));
+        for server in servers {
+            if self.alerts_in_flight.contains(&server) {
+                continue;
+            }
+            let client = match self.client(Some(&server)) {
+                Ok(c) => c,
+                Err(e) => {
+                    self.alerts.insert(server, Err(e.to_string()));
+                    continue;
+                }
+            };
+            self.alerts_in_flight.insert(server.clone());
+            let tx = self.alerts_tx.clone();
+            tokio::spawn(async move {
+                let timeout = Duration::from_secs(DEFAULT_REQUEST_TIMEOUT_SECS);
+                let outcome = match tokio::time::timeout(timeout, client.alerting_rules()).await {
+                    Ok(r) => r,
+                    Err(_) => Err(ClientError::Timeout(timeout))
+                };
+                let _ = tx.send(AlertsResult { server, outcome });
+            });
+        }
+    }
+
+    fn drain_alerts(&mut self) {
+        while let Ok(result) = self.alerts_rx.try_recv() {
+            self.alerts_in_flight.remove(&result.server);
+            let alerts = match result.outcome {
+                Ok(r) => Ok(active_alerts(&result.server, r.data)),
+                // not every server has rules, so this stays out of the log pane unless asked for
+                Err(e) => {
+                    debug!("alerts from {}: {}", result.server, e);
+                    Err(e.to_string())
+                }
+            };
+            self.alerts.insert(result.server, alerts);
+        }
+        self.alerts_move(0);
+    }
+    //endregion
+
+    //region Config Reload
+    pub fn request_reload(&self) {
+        self.reload_requested.store(true, Ordering::Relaxed);
+    }
+
+    #[cfg(unix)]
+    pub fn reload_on_sighup(&self) {
+        let requested = self.reload_requested.clone();
+        let mut hangup = match signal(SignalKind::hangup()) {
             Ok(s) => s,
             Err(e) => {
-                warn!("unable to parse promresponse from json: {}", e);
-                bail!("unable to parse promresponse from json: {}", e);
+                warn!("unable to listen for SIGHUP: {}", e);
+                return;
+            }
+        };
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                requested.store(true, Ordering::Relaxed);
+            }
+        });
+    }
+
+    // reloads when asked to, or when a config file has been written since we last looked
+    fn poll_config(&mut self) {
+        let requested = self.reload_requested.swap(false, Ordering::Relaxed);
+        if !requested && self.last_config_poll.elapsed() < Duration::from_secs(CONFIG_POLL_INTERVAL_SECS) {
+            return;
+        }
+        self.last_config_poll = Instant::now();
+        let stamps = self.config.search().stamps();
+        if !requested && stamps == self.config_stamps {
+            return;
+        }
+        // remember the stamps even if the reload fails, so a broken edit is reported once
+        self.config_stamps = stamps;
+        self.reload_config();
+    }
+
+    // a config that fails to load or validate is reported and the current one kept
+    fn reload_config(&mut self) {
+        let config = match ConfigFile::new(self.config.path.clone()) {
+            Ok(c) => c,
+            Err(e) => {
+                warn!("Config reload failed, keeping the previous config: {}", e);
+                return;
             }
         };
-        let rsdata = match rsjson.data {
-            Some(data) => data,
+        let errors = validate(&config, &self.vars);
+        if let Some(first) = errors.first() {
+            warn!("Config reload rejected with {} error(s), keeping the previous config: {}", errors.len(), first);
+            return;
+        }
+        self.config = config;
+        // rebuilt on next use, picking up changed server settings and rotated token files
+        self.clients.clear();
+        self.completion_cache.clear();
+        info!("Reloaded config from {}", itertools::join(self.config.sources.iter().map(|p| p.display()), ", "));
+        self.rebuild_panels();
+    }
+
+    // rebuilds the current view against the new config, keeping focus, time range and variable picks
+    fn rebuild_panels(&mut self) {
+        let focused = self.focused;
+        match self.dashboard.clone() {
+            Some(name) => {
+                let previous = std::mem::take(&mut self.variables);
+                let vars = self.vars.clone();
+                self.set_dashboard(&name, &vars);
+                for v in self.variables.iter_mut() {
+                    if let Some(old) = previous.iter().find(|old| old.def.name == v.def.name) {
+                        v.selected = old.selected.clone();
+                        v.all = old.all;
+                    }
+                }
+            }
             None => {
-                warn!("Didn't get any data back from query?");
-                bail!("Didn't get any data back from query?");
+                for i in 0..self.panels.len() {
+                    self.cancel_fetch(i);
+                }
+                match self.panels.pop() {
+                    Some(p) => self.set_target(p.target),
+                    None => return
+                }
             }
+        }
+        self.focused = focused.min(self.panels.len().saturating_sub(1));
+    }
+    //endregion
+
+    fn set_panels(&mut self, dashboard: Option<String>, columns: u16, panels: Vec<Panel>) {
+        for i in 0..self.panels.len() {
+            self.cancel_fetch(i);
+        }
+        self.dashboard = dashboard;
+        self.columns = columns;
+        self.panels = panels;
+        self.focused = 0;
+        self.cursor = None;
+        self.variables.clear();
+        self.picker = None;
+        self.generation += 1;
+        self.refresh_requested = true;
+    }
+
+    pub fn focus_next(&mut self) {
+        if !self.panels.is_empty() {
+            self.focused = (self.focused + 1) % self.panels.len();
+        }
+    }
+
+    pub fn focus_prev(&mut self) {
+        if !self.panels.is_empty() {
+            self.focused = (self.focused + self.panels.len() - 1) % self.panels.len();
+        }
+    }
+
+    // `s` and `S` on a focused table panel
+    pub fn cycle_sort(&mut self) {
+        if let Some(p) = self.panels.get_mut(self.focused).filter(|p| p.kind == PanelKind::Table) {
+            p.cycle_sort();
+        }
+    }
+
+    pub fn reverse_sort(&mut self) {
+        if let Some(p) = self.panels.get_mut(self.focused).filter(|p| p.kind == PanelKind::Table) {
+            p.sort_desc = !p.sort_desc;
+        }
+    }
+
+    //region Time Range Control
+    pub fn current_window(&self) -> (f64, f64) {
+        let end = self.range_end.unwrap_or_else(now_secs);
+        (end - self.range_secs, end)
+    }
+
+    pub fn set_range(&mut self, secs: f64) {
+        self.range_secs = secs.clamp(MIN_TUI_RANGE_SECS, MAX_TUI_RANGE_SECS);
+        self.refresh_requested = true;
+    }
+
+    pub fn zoom_in(&mut self) {
+        self.set_range(self.range_secs / 2.0);
+    }
+
+    pub fn zoom_out(&mut self) {
+        self.set_range(self.range_secs * 2.0);
+    }
+
+    pub fn pan_back(&mut self) {
+        let (_, end) = self.current_window();
+        self.range_end = Some(end - self.range_secs * TUI_PAN_RATIO);
+        self.refresh_requested = true;
+    }
+
+    // panning past the present resumes following "now"
+    pub fn pan_forward(&mut self) {
+        let (_, end) = self.current_window();
+        let new_end = end + self.range_secs * TUI_PAN_RATIO;
+        self.range_end = if new_end >= now_secs() { None } else { Some(new_end) };
+        self.refresh_requested = true;
+    }
+
+    pub fn jump_to_now(&mut self) {
+        self.range_end = None;
+        self.refresh_requested = true;
+    }
+
+    pub fn range_label(&self) -> String {
+        let (start, end) = self.current_window();
+        let span = humantime::format_duration(Duration::from_secs(self.range_secs as u64));
+        format!("{} → {} ({}, {})",
+            format_tick(start, self.range_secs),
+            format_tick(end, self.range_secs),
+            span,
+            if self.range_end.is_none() { "live" } else { "paused" })
+    }
+    //endregion
+
+    //region Crosshair Cursor
+    fn focused_times(&self) -> Vec<f64> {
+        match self.panels.get(self.focused) {
+            Some(p) => p.sample_times(),
+            None => vec![]
+        }
+    }
+
+    pub fn cursor_left(&mut self) {
+        let times = self.focused_times();
+        self.cursor = match self.cursor {
+            Some(c) => times.iter().rev().find(|t| **t < c).or(times.first()).copied(),
+            None => times.last().copied()
         };
-        let retval: Vec<(f64, f64)> = Vec::new();
-        debug!("rsdata contains {} results", rsdata.result.len());
-        rsdata.result.iter().for_each(|datum| {
-            debug!("datum contains {} points", datum.values.len());
-            datum.values.iter().for_each(|value| {
-                debug!("value[0] is {:#?}, value[1] is {:#?}", value[0], value[1]);
-                //self.data.push((value[0].as_f64().unwrap(), value[1].as_f64().unwrap()));
-            });
+    }
+
+    pub fn cursor_right(&mut self) {
+        let times = self.focused_times();
+        self.cursor = match self.cursor {
+            Some(c) => times.iter().find(|t| **t > c).or(times.last()).copied(),
+            None => times.last().copied()
+        };
+    }
+
+    // hovering a panel focuses it and snaps the cursor to that panel's nearest sample
+    pub fn cursor_at_column(&mut self, column: u16, row: u16) {
+        let idx = match self.panels.iter().position(|p| p.contains(column, row)) {
+            Some(i) => i,
+            None => return
+        };
+        self.focused = idx;
+        let t = self.panels[idx].time_at_column(column);
+        self.cursor = nearest(&self.panels[idx].sample_times(), t);
+    }
+    //endregion
+
+    pub fn on_tick(&mut self) {
+        self.poll_config();
+        self.drain_completions();
+        self.drain_alerts();
+        self.poll_alerts();
+        self.drain_variables();
+        self.drain_fetches();
+        if !self.variables_ready() {
+            return;
+        }
+        let stale = self.range_end.is_none()
+            && self.panels.iter().all(|p| p.in_flight.is_none())
+            && self.last_refresh.elapsed() >= Duration::from_secs(MINIMUM_SERVER_WAIT_SECS);
+        if stale || self.refresh_requested {
+            self.refresh_requested = false;
+            for i in 0..self.panels.len() {
+                if self.panels[i].target.source.is_none() {
+                    continue;
+                }
+                if let Err(e) = self.start_fetch(i) {
+                    warn!("{}", e);
+                    self.panels[i].error = Some(e.to_string());
+                }
+            }
+            self.last_refresh = Instant::now();
+        }
+    }
+
+    pub fn in_flight_secs(&self) -> Option<f64> {
+        match self.panels.iter().any(|p| p.in_flight.is_some()) {
+            true => Some(self.last_request.elapsed().as_secs_f64()),
+            false => None
+        }
+    }
+
+    // Spawns the panel's range query on a background task; any request still in flight for it is abandoned.
+    fn start_fetch(&mut self, panel: usize) -> Result<(), ClientError> {
+        let server = self.panels[panel].target.server.clone();
+        let client = self.client(Some(&server))?;
+        let target = &self.panels[panel].target;
+        let interp_string = target.render(&self.config)?;
+        let (query_start, query_end) = self.current_window();
+        let step = auto_step(query_start, query_end);
+        let instant = self.panels[panel].kind.is_instant();
+
+        self.cancel_fetch(panel);
+        self.next_request_id += 1;
+        let id = self.next_request_id;
+        let tx = self.fetch_tx.clone();
+        let handle = tokio::spawn(async move {
+            let timeout = Duration::from_secs(DEFAULT_REQUEST_TIMEOUT_SECS);
+            let request = async {
+                if instant {
+                    client.query(&interp_string, query_end).await
+                } else {
+                    client.query_range(&interp_string, query_start, query_end, step).await
+                }
+            };
+            let outcome = match tokio::time::timeout(timeout, request).await {
+                Ok(r) => r,
+                Err(_) => Err(ClientError::Timeout(timeout))
+            };
+            // the receiver only goes away when the app is shutting down
+            let _ = tx.send(FetchResult { panel, id, window: (query_start, query_end), outcome });
         });
-        Ok(retval)
+        debug!("started fetch {} for panel {}", id, panel);
+        self.panels[panel].in_flight = Some(InFlight { id, handle });
+        self.last_request = Instant::now();
+        Ok(())
+    }
+
+    pub fn cancel_fetch(&mut self, panel: usize) {
+        if let Some(f) = self.panels.get_mut(panel).and_then(|p| p.in_flight.take()) {
+            debug!("cancelling fetch {}", f.id);
+            f.handle.abort();
+        }
+    }
+
+    fn drain_fetches(&mut self) {
+        while let Ok(result) = self.fetch_rx.try_recv() {
+            let panel = match self.panels.get_mut(result.panel) {
+                Some(p) if p.in_flight.as_ref().map(|f| f.id) == Some(result.id) => p,
+                _ => {
+                    debug!("dropping stale fetch {}", result.id);
+                    continue;
+                }
+            };
+            panel.in_flight = None;
+            match result.outcome {
+                Ok(r) => {
+                    for w in &r.warnings {
+                        warn!("Prometheus warning: {}", w);
+                    }
+                    panel.error = None;
+                    // the named query behind the panel may have changed since the panel was built
+                    panel.format = panel.target.format(&self.config);
+                    if panel.kind == PanelKind::Heatmap {
+                        match Heatmap::from_data(&r.data) {
+                            Ok(h) => panel.heatmap = Some(h),
+                            Err(e) => panel.error = Some(e)
+                        }
+                    }
+                    let series = panel.to_series(r.data, &self.config);
+                    panel.window = result.window;
+                    panel.data = series;
+                }
+                Err(e) => {
+                    warn!("{}: {}", panel.title, e);
+                    panel.error = Some(e.to_string());
+                }
+            }
+        }
     }
 }
 
+fn nearest(sorted: &[f64], t: f64) -> Option<f64> {
+    sorted.iter()
+        .min_by(|a, b| (**a - t).abs().total_cmp(&(**b - t).abs()))
+        .copied()
+}
+
 fn make_menu() -> MenuState<Cow<'static, str>> {
     MenuState::new(vec![
         MenuItem::group(
             "File",
-            vec![MenuItem::item("Exit", "exit".into())],
+            vec![
+                MenuItem::item("Alerts", "alerts".into()),
+                MenuItem::item("Reload config", "reload".into()),
+                MenuItem::item("Exit", "exit".into()),
+            ],
+        ),
+        MenuItem::group(
+            "Range",
+            TUI_RANGE_PRESETS.iter()
+                .map(|p| MenuItem::item(*p, format!("range:{}", p).into()))
+                .collect(),
         ),
         MenuItem::group(
             "Help",
             vec![MenuItem::item("About", "about".into())]),
     ])
-}
\ No newline at end of file
+}
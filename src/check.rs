@@ -0,0 +1,378 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt::Display;
+use std::time::Duration;
+use handlebars::template::{HelperTemplate, Parameter, Template, TemplateElement};
+use reqwest::{StatusCode, Url};
+use crate::cfg_file::{redact_url, ConfigFile, DashboardRef, PanelRef};
+use crate::cli::Check;
+use crate::client::{render_template, ClientError, PromClient};
+use crate::consts::*;
+use crate::variables::parse_label_values;
+
+enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Default)]
+struct Report {
+    problems: Vec<(Severity, String)>,
+}
+
+impl Report {
+    fn error(&mut self, what: &str, message: impl Display) {
+        self.problems.push((Severity::Error, format!("{}: {}", what, message)));
+    }
+
+    fn warning(&mut self, what: &str, message: impl Display) {
+        self.problems.push((Severity::Warning, format!("{}: {}", what, message)));
+    }
+
+    fn errors(&self) -> Vec<String> {
+        self.problems.iter()
+            .filter(|(s, _)| matches!(s, Severity::Error))
+            .map(|(_, m)| m.clone())
+            .collect()
+    }
+}
+
+// an expression to hand to /api/v1/parse_query once the static checks are done
+struct ParseJob {
+    what: String,
+    server: String,
+    template: String,
+    vars: BTreeMap<String, String>,
+}
+
+// Runs every check and prints one line per problem, returning the number of errors.
+pub async fn check_config(config: &ConfigFile, args: &Check) -> usize {
+    for source in &config.sources {
+        println!("checking {}", source.display());
+    }
+    let default_server = args.server.clone().unwrap_or_else(|| DEFAULT_SERVER_NAME.to_string());
+    let (mut report, jobs) = check_static(config, &default_server, &BTreeMap::new());
+    if args.parse {
+        check_syntax(&mut report, config, jobs).await;
+    }
+
+    let mut warnings = 0;
+    for (severity, message) in &report.problems {
+        match severity {
+            Severity::Error => println!("error: {}", message),
+            Severity::Warning => {
+                println!("warning: {}", message);
+                warnings += 1;
+            }
+        }
+    }
+    let errors = report.problems.len() - warnings;
+    println!("{} error(s), {} warning(s)", errors, warnings);
+    errors
+}
+
+// The errors `config check` would report without contacting any server; `vars` are the -e
+// values the config will run with, which templates may use without the dashboard declaring them.
+pub fn validate(config: &ConfigFile, vars: &BTreeMap<String, String>) -> Vec<String> {
+    check_static(config, DEFAULT_SERVER_NAME, vars).0.errors()
+}
+
+fn check_static(config: &ConfigFile, default_server: &str, vars: &BTreeMap<String, String>) -> (Report, Vec<ParseJob>) {
+    let mut report = Report::default();
+    let mut jobs = vec![];
+
+    check_duplicates(&mut report, "servers", config.servers.iter().map(|s| s.name.as_str()));
+    check_duplicates(&mut report, "queries", config.queries.iter().map(|q| q.name.as_str()));
+    check_duplicates(&mut report, "dashboards", config.dashboards.iter().map(|d| d.name.as_str()));
+
+    //region servers
+    for server in &config.servers {
+        let what = format!("servers.{}", server.name);
+        // the url may carry resolved credentials
+        let shown = redact_url(&server.url);
+        match Url::parse(&server.url) {
+            Ok(url) if url.scheme() != "http" && url.scheme() != "https" => {
+                report.error(&what, format!("url '{}' must be http or https", shown))
+            }
+            Ok(url) if url.host_str().is_none() => report.error(&what, format!("url '{}' has no host", shown)),
+            Ok(_) => {}
+            Err(e) => report.error(&what, format!("invalid url '{}': {}", shown, e))
+        }
+        if let Err(e) = PromClient::new(server) {
+            report.error(&what, e);
+        }
+    }
+    if !config.servers.iter().any(|s| s.name == DEFAULT_SERVER_NAME) {
+        report.warning("servers", format!("there is no server named '{}', so every command will need -s", DEFAULT_SERVER_NAME));
+    }
+    //endregion
+
+    //region queries
+    for query in &config.queries {
+        let what = format!("queries.{}", query.name);
+        if let Some(vars) = check_template(&mut report, &what, &query.query) {
+            jobs.push(ParseJob {
+                what: what.clone(),
+                server: default_server.to_string(),
+                template: query.query.clone(),
+                vars: vars.into_iter().map(|v| (v, DEFAULT_ALL_VALUE.to_string())).collect(),
+            });
+        }
+        if let Some(legend) = &query.legend {
+            check_template(&mut report, &format!("{}.legend", what), legend);
+        }
+    }
+    //endregion
+
+    //region dashboards
+    for dashboard in &config.dashboards {
+        check_dashboard(&mut report, &mut jobs, config, dashboard, vars);
+    }
+    //endregion
+
+    (report, jobs)
+}
+
+fn check_duplicates<'a>(report: &mut Report, what: &str, names: impl Iterator<Item=&'a str>) {
+    let mut seen = HashSet::new();
+    let mut reported = HashSet::new();
+    for name in names {
+        if !seen.insert(name) && reported.insert(name) {
+            report.error(what, format!("'{}' is defined more than once; only the first is used", name));
+        }
+    }
+}
+
+fn check_server_ref(report: &mut Report, what: &str, config: &ConfigFile, server: &str) {
+    if !config.servers.iter().any(|s| s.name == server) {
+        report.error(what, format!("refers to server '{}', which is not defined", server));
+    }
+}
+
+fn check_dashboard(report: &mut Report, jobs: &mut Vec<ParseJob>, config: &ConfigFile, dashboard: &DashboardRef, vars: &BTreeMap<String, String>) {
+    let what = format!("dashboards.{}", dashboard.name);
+    let server = dashboard.server.clone().unwrap_or_else(|| DEFAULT_SERVER_NAME.to_string());
+    check_server_ref(report, &what, config, &server);
+    if dashboard.columns == 0 {
+        report.error(&what, "columns must be at least 1");
+    }
+    if dashboard.panels.is_empty() {
+        report.warning(&what, "has no panels");
+    }
+
+    check_duplicates(report, &format!("{}.variables", what), dashboard.variables.iter().map(|v| v.name.as_str()));
+    // dashboard variables take precedence over -e values of the same name, as they do at runtime
+    let mut placeholders = vars.clone();
+    for var in &dashboard.variables {
+        let var_what = format!("{}.variables.{}", what, var.name);
+        if let Err(e) = parse_label_values(&var.query) {
+            report.error(&var_what, e);
+        }
+        if let Some(s) = &var.server {
+            check_server_ref(report, &var_what, config, s);
+        }
+        let placeholder = var.default.clone()
+            .or_else(|| var.all_value.clone())
+            .unwrap_or_else(|| DEFAULT_ALL_VALUE.to_string());
+        placeholders.insert(var.name.clone(), placeholder);
+    }
+
+    for (i, panel) in dashboard.panels.iter().enumerate() {
+        let panel_what = match &panel.title {
+            Some(t) => format!("{}.panels[{}] '{}'", what, i, t),
+            None => format!("{}.panels[{}]", what, i)
+        };
+        let panel_server = panel.server.clone().unwrap_or_else(|| server.clone());
+        if panel.server.is_some() {
+            check_server_ref(report, &panel_what, config, &panel_server);
+        }
+        if let Some(template) = panel_template(report, &panel_what, config, panel) {
+            if let Ok(vars) = template_vars(&template) {
+                for var in vars.iter().filter(|v| !placeholders.contains_key(*v)) {
+                    report.error(&panel_what, format!("uses template variable '{}', which the dashboard does not declare", var));
+                }
+                jobs.push(ParseJob { what: panel_what.clone(), server: panel_server, template, vars: placeholders.clone() });
+            }
+        }
+        if let Some(legend) = &panel.legend {
+            check_template(report, &format!("{}.legend", panel_what), legend);
+        }
+        for threshold in panel.thresholds.iter().filter(|t| t.color().is_none()) {
+            report.error(&panel_what, format!("threshold {} has an unknown color '{}'", threshold.value, threshold.color));
+        }
+        for q in panel.quantiles.iter().filter(|q| !(**q > 0.0 && **q < 1.0)) {
+            report.error(&panel_what, format!("quantile {} must be between 0 and 1", q));
+        }
+        if let (Some(min), Some(max)) = (panel.min, panel.max) {
+            if min >= max {
+                report.error(&panel_what, format!("min {} must be less than max {}", min, max));
+            }
+        }
+    }
+}
+
+// the panel's query template, or None when it has no usable one
+fn panel_template(report: &mut Report, what: &str, config: &ConfigFile, panel: &PanelRef) -> Option<String> {
+    match (&panel.query, &panel.expr) {
+        (Some(_), Some(expr)) => {
+            report.warning(what, "sets both query and expr; expr is used");
+            check_template(report, what, expr).map(|_| expr.clone())
+        }
+        (None, Some(expr)) => check_template(report, what, expr).map(|_| expr.clone()),
+        (Some(name), None) => match config.queries.iter().find(|q| &q.name == name) {
+            Some(q) => Some(q.query.clone()),
+            None => {
+                report.error(what, ClientError::QueryNotFound(name.clone()));
+                None
+            }
+        },
+        (None, None) => {
+            report.error(what, "has neither a query nor an expr");
+            None
+        }
+    }
+}
+
+fn check_template(report: &mut Report, what: &str, template: &str) -> Option<BTreeSet<String>> {
+    match template_vars(template) {
+        Ok(vars) => Some(vars),
+        Err(e) => {
+            match (e.line_no, e.column_no) {
+                (Some(line), Some(col)) => report.error(what, format!("invalid template at {}:{}: {}", line, col, e.reason())),
+                _ => report.error(what, format!("invalid template: {}", e.reason()))
+            }
+            None
+        }
+    }
+}
+
+// the top-level variables a handlebars template reads, e.g. {"podex"} for `up{pod=~"{{podex}}"}`
+// the error is boxed because handlebars' is large
+pub fn template_vars(template: &str) -> Result<BTreeSet<String>, Box<handlebars::TemplateError>> {
+    let compiled = Template::compile(template).map_err(Box::new)?;
+    let mut vars = BTreeSet::new();
+    collect_vars(&compiled, &mut vars);
+    Ok(vars)
+}
+
+fn collect_vars(template: &Template, vars: &mut BTreeSet<String>) {
+    for element in &template.elements {
+        match element {
+            TemplateElement::Expression(h) | TemplateElement::HtmlExpression(h) | TemplateElement::HelperBlock(h) => {
+                collect_helper_vars(h, vars)
+            }
+            _ => {}
+        }
+    }
+}
+
+fn collect_helper_vars(helper: &HelperTemplate, vars: &mut BTreeSet<String>) {
+    // a bare {{name}} is a variable, otherwise the name is a helper and its arguments are the variables
+    if helper.params.is_empty() && helper.hash.is_empty() && !helper.block {
+        collect_param_vars(&helper.name, vars);
+    }
+    for param in helper.params.iter().chain(helper.hash.values()) {
+        collect_param_vars(param, vars);
+    }
+    // each/with change the context, so names inside them aren't top-level variables
+    if !matches!(helper.name.as_name(), Some("each") | Some("with")) {
+        if let Some(t) = &helper.template {
+            collect_vars(t, vars);
+        }
+    }
+    if let Some(t) = &helper.inverse {
+        collect_vars(t, vars);
+    }
+}
+
+fn collect_param_vars(param: &Parameter, vars: &mut BTreeSet<String>) {
+    match param {
+        Parameter::Name(_) | Parameter::Path(_) => {
+            if let Some(root) = param.as_name().and_then(|n| n.split(['.', '/', '[']).next()) {
+                if !root.is_empty() && !root.starts_with('@') && root != "this" {
+                    vars.insert(root.to_string());
+                }
+            }
+        }
+        Parameter::Subexpression(sub) => {
+            if let TemplateElement::Expression(h) = sub.element.as_ref() {
+                collect_helper_vars(h, vars);
+            }
+        }
+        Parameter::Literal(_) => {}
+    }
+}
+
+// renders each expression with placeholder variable values and asks its server to parse it
+async fn check_syntax(report: &mut Report, config: &ConfigFile, jobs: Vec<ParseJob>) {
+    let timeout = Duration::from_secs(DEFAULT_REQUEST_TIMEOUT_SECS);
+    let mut unsupported = HashSet::new();
+    for job in jobs {
+        if unsupported.contains(&job.server) {
+            continue;
+        }
+        let client = match PromClient::from_config(config, Some(&job.server)) {
+            Ok(c) => c,
+            // a missing or broken server has already been reported above
+            Err(_) => continue
+        };
+        let expr = match render_template(&job.template, &job.vars) {
+            Ok(e) => e,
+            Err(e) => {
+                report.error(&job.what, e);
+                continue;
+            }
+        };
+        let outcome = match tokio::time::timeout(timeout, client.parse_query(&expr)).await {
+            Ok(r) => r,
+            Err(_) => Err(ClientError::Timeout(timeout))
+        };
+        match outcome {
+            Ok(_) => {}
+            Err(ClientError::Api { message, .. }) => {
+                report.error(&job.what, format!("PromQL syntax error in '{}': {}", expr.trim(), message))
+            }
+            Err(ClientError::Decode { status: StatusCode::NOT_FOUND, .. }) => {
+                report.warning(&format!("servers.{}", job.server), "does not support /api/v1/parse_query, skipping syntax checks");
+                unsupported.insert(job.server);
+            }
+            Err(e) => {
+                report.warning(&format!("servers.{}", job.server), format!("{}, skipping syntax checks", e));
+                unsupported.insert(job.server);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn load(toml: &str) -> ConfigFile {
+        let path = std::env::temp_dir().join(format!("clifana-check-test-{}.toml", std::process::id()));
+        std::fs::write(&path, toml).unwrap();
+        let config = ConfigFile::new(Some(PathBuf::from(&path)));
+        std::fs::remove_file(&path).unwrap();
+        config.unwrap()
+    }
+
+    #[test]
+    fn eval_values_count_as_declared_on_reload() {
+        let config = load(r#"
+            [[servers]]
+            name = "default"
+            url = "http://localhost:9090"
+
+            [[dashboards]]
+            name = "nodes"
+            [[dashboards.panels]]
+            expr = 'up{cluster="{{cluster}}"}'
+        "#);
+        let errors = validate(&config, &BTreeMap::new());
+        assert!(errors.len() == 1 && errors[0].contains("'cluster'"), "{:?}", errors);
+
+        // started with -e cluster=prod, so a reload of the same file must be accepted
+        let vars = BTreeMap::from([("cluster".to_string(), "prod".to_string())]);
+        assert!(validate(&config, &vars).is_empty());
+    }
+}
@@ -1,5 +1,8 @@
 use std::path::PathBuf;
 use clap::{Args, Parser, Subcommand};
+use crate::consts::*;
+use crate::output::OutputFormat;
+use crate::units::Unit;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -11,12 +14,39 @@ pub struct Cli {
     pub debug: u8,
 
     #[command(subcommand)]
-    pub command: Commands
+    pub command: Option<Commands>
 }
 
 #[derive(Subcommand)]
 pub enum Commands {
-    Query(Query)
+    /// Run a one-shot instant query and print the results to stdout
+    Query(Query),
+    /// Run a range query and print the resulting matrix to stdout
+    Range(Range),
+    /// Launch the interactive dashboard (the default when no subcommand is given)
+    #[command(alias = "watch")]
+    Tui(Tui),
+    /// Inspect the configuration
+    #[command(subcommand)]
+    Config(ConfigCommands)
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommands {
+    /// List the config files that are searched, in merge order, and which of them were loaded
+    Path,
+    /// Validate names, references, urls and query templates, exiting non-zero on errors
+    Check(Check)
+}
+
+#[derive(Args, Debug)]
+pub struct Check {
+    /// Also check PromQL syntax with each server's /api/v1/parse_query
+    #[arg(long)]
+    pub parse: bool,
+    /// Server to check named queries against when --parse is given
+    #[arg(short='s',long="server")]
+    pub server: Option<String>
 }
 
 #[derive(Args, Debug)]
@@ -25,6 +55,65 @@ pub struct Query {
     #[arg(short='s',long="server")]
     pub server: Option<String>,
     #[arg(short='e',long="eval")]
-    pub eval: Vec<String>
+    pub eval: Vec<String>,
+    /// How to show values, e.g. bytes, s or percent; overrides the query's unit in the config
+    #[arg(short='u',long="unit", value_enum)]
+    pub unit: Option<Unit>,
+    /// Number of decimals to show values with
+    #[arg(long="decimals")]
+    pub decimals: Option<usize>,
+    #[arg(short='o',long="output", value_enum, default_value_t=OutputFormat::Table)]
+    pub output: OutputFormat
+}
+
+#[derive(Args, Debug)]
+pub struct Range {
+    pub query: Option<String>,
+    #[arg(short='s',long="server")]
+    pub server: Option<String>,
+    #[arg(short='e',long="eval")]
+    pub eval: Vec<String>,
+    /// RFC3339 timestamp, unix epoch or relative expression like now-6h or -30m
+    #[arg(long="start", default_value=DEFAULT_RANGE_START)]
+    pub start: String,
+    /// RFC3339 timestamp, unix epoch or relative expression like now or now-1h
+    #[arg(long="end", default_value=DEFAULT_RANGE_END)]
+    pub end: String,
+    /// Resolution step such as 30s or 5m; computed from the range when omitted
+    #[arg(long="step")]
+    pub step: Option<String>,
+    /// How to show values, e.g. bytes, s or percent; overrides the query's unit in the config
+    #[arg(short='u',long="unit", value_enum)]
+    pub unit: Option<Unit>,
+    /// Number of decimals to show values with
+    #[arg(long="decimals")]
+    pub decimals: Option<usize>,
+    #[arg(short='o',long="output", value_enum, default_value_t=OutputFormat::Table)]
+    pub output: OutputFormat
+}
 
+#[derive(Args, Debug, Default)]
+pub struct Tui {
+    /// Name of a query from the config
+    #[arg(conflicts_with_all = ["expr", "dashboard"])]
+    pub query: Option<String>,
+    /// Open a [[dashboards]] entry from the config
+    #[arg(long="dashboard", value_name = "NAME", conflicts_with = "expr")]
+    pub dashboard: Option<String>,
+    /// Inline PromQL expression to chart instead of a named query
+    #[arg(short='x',long="expr")]
+    pub expr: Option<String>,
+    #[arg(short='s',long="server")]
+    pub server: Option<String>,
+    #[arg(short='e',long="eval")]
+    pub eval: Vec<String>,
+    /// Legend template over series labels, e.g. '{{pod}} on {{instance}}'
+    #[arg(short='l',long="legend")]
+    pub legend: Option<String>,
+    /// How to show values, e.g. bytes, s or percent; overrides the query's unit in the config
+    #[arg(short='u',long="unit", value_enum)]
+    pub unit: Option<Unit>,
+    /// Number of decimals to show values with
+    #[arg(long="decimals")]
+    pub decimals: Option<usize>
 }
\ No newline at end of file
@@ -0,0 +1,263 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use handlebars::Handlebars;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::RequestBuilder;
+use serde::de::DeserializeOwned;
+use crate::cfg_file::{redact_url, ConfigFile, ServerRef};
+use crate::consts::*;
+use crate::prometheus::{Metadata, PromResponse, QueryData, RulesData};
+
+#[derive(Debug)]
+pub enum ClientError {
+    ServerNotFound(String),
+    QueryNotFound(String),
+    Template(String),
+    // reqwest's error with its url taken out, and the url with any credentials masked
+    Http { error: reqwest::Error, url: Option<String> },
+    Decode { status: reqwest::StatusCode, message: String },
+    Api { error_type: String, message: String },
+    Timeout(std::time::Duration),
+    Config(String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::ServerNotFound(name) => write!(f, "Can't find a server named '{}', please add it to the config (see `clifana config path`) or specify another server via -s", name),
+            ClientError::QueryNotFound(name) => write!(f, "There was no query named '{}'", name),
+            ClientError::Template(e) => write!(f, "Unable to render query template: {}", e),
+            ClientError::Http { error, url: Some(url) } => write!(f, "HTTP request to {} failed: {}", url, error),
+            ClientError::Http { error, url: None } => write!(f, "HTTP request failed: {}", error),
+            ClientError::Decode { status, message } => write!(f, "Unable to parse response from server (HTTP {}): {}", status, message),
+            ClientError::Api { error_type, message } => write!(f, "Prometheus returned {}: {}", error_type, message),
+            ClientError::Config(e) => write!(f, "Server configuration error: {}", e),
+            ClientError::Timeout(d) => write!(f, "Request timed out after {}", humantime::format_duration(*d)),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(e: reqwest::Error) -> Self {
+        let url = e.url().map(|u| redact_url(u.as_str()));
+        ClientError::Http { error: e.without_url(), url }
+    }
+}
+
+impl From<handlebars::RenderError> for ClientError {
+    fn from(e: handlebars::RenderError) -> Self {
+        ClientError::Template(e.to_string())
+    }
+}
+
+// A successful API call, along with any warnings prometheus attached and the raw body.
+#[derive(Debug, Clone)]
+pub struct ApiResult<T> {
+    pub data: T,
+    pub warnings: Vec<String>,
+    pub raw: String,
+}
+
+#[derive(Clone)]
+enum Auth {
+    None,
+    Basic { username: String, password: Option<String> },
+    Bearer(String),
+}
+
+#[derive(Clone)]
+pub struct PromClient {
+    base_url: String,
+    // base_url with credentials masked, for logging
+    display_url: String,
+    http: reqwest::Client,
+    auth: Auth,
+}
+
+fn read_secret(path: &Path, what: &str) -> Result<String, ClientError> {
+    match std::fs::read_to_string(path) {
+        Ok(s) => Ok(s.trim().to_string()),
+        Err(e) => Err(ClientError::Config(format!("unable to read {} from {}: {}", what, path.display(), e)))
+    }
+}
+
+fn read_pem(path: &Path, what: &str) -> Result<Vec<u8>, ClientError> {
+    std::fs::read(path).map_err(|e| ClientError::Config(format!("unable to read {} from {}: {}", what, path.display(), e)))
+}
+
+impl PromClient {
+    pub fn new(server: &ServerRef) -> Result<Self, ClientError> {
+        let mut headers = HeaderMap::new();
+        for (k, v) in &server.headers {
+            let name = HeaderName::from_bytes(k.as_bytes())
+                .map_err(|e| ClientError::Config(format!("invalid header name '{}': {}", k, e)))?;
+            let value = HeaderValue::from_str(v)
+                .map_err(|e| ClientError::Config(format!("invalid value for header '{}': {}", k, e)))?;
+            headers.insert(name, value);
+        }
+        let mut builder = reqwest::Client::builder()
+            .default_headers(headers)
+            .danger_accept_invalid_certs(server.insecure_skip_verify);
+        if let Some(ca) = &server.ca_file {
+            let cert = reqwest::Certificate::from_pem(&read_pem(ca, "CA bundle")?)
+                .map_err(|e| ClientError::Config(format!("invalid CA bundle {}: {}", ca.display(), e)))?;
+            builder = builder.add_root_certificate(cert);
+        }
+        match (&server.cert_file, &server.key_file) {
+            (Some(cert), Some(key)) => {
+                let identity = reqwest::Identity::from_pkcs8_pem(&read_pem(cert, "client certificate")?, &read_pem(key, "client key")?)
+                    .map_err(|e| ClientError::Config(format!("invalid client certificate/key: {}", e)))?;
+                builder = builder.identity(identity);
+            }
+            (None, None) => {}
+            _ => return Err(ClientError::Config("cert_file and key_file must be set together".to_string()))
+        }
+
+        let auth = match (&server.basic_auth, &server.bearer_token, &server.bearer_token_file) {
+            (Some(_), Some(_), _) | (Some(_), _, Some(_)) | (_, Some(_), Some(_)) => {
+                return Err(ClientError::Config("only one of basic_auth, bearer_token and bearer_token_file may be set".to_string()));
+            }
+            (Some(basic), None, None) => {
+                let password = match (&basic.password, &basic.password_file) {
+                    (Some(p), _) => Some(p.clone()),
+                    (None, Some(f)) => Some(read_secret(f, "password")?),
+                    (None, None) => None
+                };
+                Auth::Basic { username: basic.username.clone(), password }
+            }
+            (None, Some(token), None) => Auth::Bearer(token.clone()),
+            (None, None, Some(f)) => Auth::Bearer(read_secret(f, "bearer token")?),
+            (None, None, None) => Auth::None
+        };
+
+        Ok(PromClient {
+            base_url: server.url.trim_end_matches('/').to_string(),
+            display_url: redact_url(server.url.trim_end_matches('/')),
+            http: builder.build()?,
+            auth,
+        })
+    }
+
+    fn authorize(&self, rb: RequestBuilder) -> RequestBuilder {
+        match &self.auth {
+            Auth::None => rb,
+            Auth::Basic { username, password } => rb.basic_auth(username, password.as_ref()),
+            Auth::Bearer(token) => rb.bearer_auth(token)
+        }
+    }
+
+    // Looks up a server by name, falling back to the server named "default".
+    pub fn from_config(config: &ConfigFile, servername: Option<&str>) -> Result<Self, ClientError> {
+        let servername = servername.unwrap_or(DEFAULT_SERVER_NAME);
+        match config.servers.iter().find(|s| s.name == servername) {
+            Some(s) => PromClient::new(s),
+            None => Err(ClientError::ServerNotFound(servername.to_string()))
+        }
+    }
+
+    pub async fn query(&self, query: &str, time: f64) -> Result<ApiResult<QueryData>, ClientError> {
+        self.post("/api/v1/query", &[
+            ("query", query.to_string()),
+            ("time", time.to_string()),
+        ]).await
+    }
+
+    pub async fn query_range(&self, query: &str, start: f64, end: f64, step: f64) -> Result<ApiResult<QueryData>, ClientError> {
+        self.post("/api/v1/query_range", &[
+            ("query", query.to_string()),
+            ("start", start.to_string()),
+            ("end", end.to_string()),
+            ("step", step.to_string()),
+        ]).await
+    }
+
+    // POST /api/v1/parse_query, which only checks the expression's syntax
+    pub async fn parse_query(&self, query: &str) -> Result<ApiResult<serde_json::Value>, ClientError> {
+        self.post("/api/v1/parse_query", &[("query", query.to_string())]).await
+    }
+
+    // GET /api/v1/label/<name>/values, optionally restricted to series matching `selector`
+    pub async fn label_values(&self, label: &str, selector: Option<&str>, start: f64, end: f64) -> Result<ApiResult<Vec<String>>, ClientError> {
+        let mut params = vec![
+            ("start", start.to_string()),
+            ("end", end.to_string()),
+        ];
+        if let Some(s) = selector {
+            params.push(("match[]", s.to_string()));
+        }
+        self.get(&format!("/api/v1/label/{}/values", label), &params).await
+    }
+
+    // GET /api/v1/labels, optionally restricted to series matching `selector`
+    pub async fn label_names(&self, selector: Option<&str>, start: f64, end: f64) -> Result<ApiResult<Vec<String>>, ClientError> {
+        let mut params = vec![
+            ("start", start.to_string()),
+            ("end", end.to_string()),
+        ];
+        if let Some(s) = selector {
+            params.push(("match[]", s.to_string()));
+        }
+        self.get("/api/v1/labels", &params).await
+    }
+
+    // GET /api/v1/rules?type=alert: the alerting rules, each with its pending and firing alerts
+    pub async fn alerting_rules(&self) -> Result<ApiResult<RulesData>, ClientError> {
+        self.get("/api/v1/rules", &[("type", "alert".to_string())]).await
+    }
+
+    // GET /api/v1/metadata: type, help and unit for every metric the server's targets expose
+    pub async fn metadata(&self) -> Result<ApiResult<Metadata>, ClientError> {
+        self.get("/api/v1/metadata", &[]).await
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str, params: &[(&str, String)]) -> Result<ApiResult<T>, ClientError> {
+        let full_url = format!("{}{}", self.base_url, path);
+        debug!("GET {}{} {:?}", self.display_url, path, params);
+        let rs = self.authorize(self.http.get(&full_url)).query(params).send().await?;
+        decode(rs).await
+    }
+
+    async fn post<T: DeserializeOwned>(&self, path: &str, form: &[(&str, String)]) -> Result<ApiResult<T>, ClientError> {
+        let full_url = format!("{}{}", self.base_url, path);
+        debug!("POST {}{} {:?}", self.display_url, path, form);
+        let rs = self.authorize(self.http.post(&full_url)).form(form).send().await?;
+        decode(rs).await
+    }
+}
+
+async fn decode<T: DeserializeOwned>(rs: reqwest::Response) -> Result<ApiResult<T>, ClientError> {
+    let status = rs.status();
+    let raw = rs.text().await?;
+    let rsjson: PromResponse<T> = match serde_json::from_str::<PromResponse<T>>(&raw) {
+        Ok(r) => r,
+        Err(e) => return Err(ClientError::Decode { status, message: e.to_string() })
+    };
+    if rsjson.status != "success" {
+        return Err(ClientError::Api {
+            error_type: rsjson.error_type.unwrap_or(rsjson.status),
+            message: rsjson.error.unwrap_or_else(|| "no error message".to_string()),
+        });
+    }
+    match rsjson.data {
+        Some(data) => Ok(ApiResult { data, warnings: rsjson.warnings, raw }),
+        None => Err(ClientError::Decode { status, message: "response contained no data".to_string() })
+    }
+}
+
+// Renders a named query from the config file with the given template variables.
+pub fn render_query(config: &ConfigFile, queryname: &str, vars: &BTreeMap<String, String>) -> Result<String, ClientError> {
+    match config.queries.iter().find(|q| q.name == queryname) {
+        Some(q) => render_template(&q.query, vars),
+        None => Err(ClientError::QueryNotFound(queryname.to_string()))
+    }
+}
+
+pub fn render_template(template: &str, vars: &BTreeMap<String, String>) -> Result<String, ClientError> {
+    let mut handlebars = Handlebars::new();
+    // PromQL is not HTML; quotes and ampersands in variables must pass through untouched
+    handlebars.register_escape_fn(handlebars::no_escape);
+    Ok(handlebars.render_template(template, vars)?)
+}
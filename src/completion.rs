@@ -0,0 +1,407 @@
+use std::collections::HashMap;
+use crate::consts::*;
+use crate::prometheus::Metadata;
+
+// PromQL functions with their signatures, shown as help text in the popup
+const FUNCTIONS: &[(&str, &str)] = &[
+    ("abs", "abs(v instant-vector)"),
+    ("absent", "absent(v instant-vector)"),
+    ("absent_over_time", "absent_over_time(v range-vector)"),
+    ("acos", "acos(v instant-vector)"),
+    ("acosh", "acosh(v instant-vector)"),
+    ("asin", "asin(v instant-vector)"),
+    ("asinh", "asinh(v instant-vector)"),
+    ("atan", "atan(v instant-vector)"),
+    ("atanh", "atanh(v instant-vector)"),
+    ("avg_over_time", "avg_over_time(v range-vector)"),
+    ("ceil", "ceil(v instant-vector)"),
+    ("changes", "changes(v range-vector)"),
+    ("clamp", "clamp(v instant-vector, min scalar, max scalar)"),
+    ("clamp_max", "clamp_max(v instant-vector, max scalar)"),
+    ("clamp_min", "clamp_min(v instant-vector, min scalar)"),
+    ("cos", "cos(v instant-vector)"),
+    ("cosh", "cosh(v instant-vector)"),
+    ("count_over_time", "count_over_time(v range-vector)"),
+    ("day_of_month", "day_of_month(v=vector(time()) instant-vector)"),
+    ("day_of_week", "day_of_week(v=vector(time()) instant-vector)"),
+    ("day_of_year", "day_of_year(v=vector(time()) instant-vector)"),
+    ("days_in_month", "days_in_month(v=vector(time()) instant-vector)"),
+    ("deg", "deg(v instant-vector)"),
+    ("delta", "delta(v range-vector)"),
+    ("deriv", "deriv(v range-vector)"),
+    ("exp", "exp(v instant-vector)"),
+    ("floor", "floor(v instant-vector)"),
+    ("histogram_avg", "histogram_avg(v instant-vector)"),
+    ("histogram_count", "histogram_count(v instant-vector)"),
+    ("histogram_fraction", "histogram_fraction(lower scalar, upper scalar, v instant-vector)"),
+    ("histogram_quantile", "histogram_quantile(φ scalar, b instant-vector)"),
+    ("histogram_stddev", "histogram_stddev(v instant-vector)"),
+    ("histogram_stdvar", "histogram_stdvar(v instant-vector)"),
+    ("histogram_sum", "histogram_sum(v instant-vector)"),
+    ("holt_winters", "holt_winters(v range-vector, sf scalar, tf scalar)"),
+    ("hour", "hour(v=vector(time()) instant-vector)"),
+    ("idelta", "idelta(v range-vector)"),
+    ("increase", "increase(v range-vector)"),
+    ("irate", "irate(v range-vector)"),
+    ("label_join", "label_join(v instant-vector, dst string, separator string, src_1 string, ...)"),
+    ("label_replace", "label_replace(v instant-vector, dst string, replacement string, src string, regex string)"),
+    ("last_over_time", "last_over_time(v range-vector)"),
+    ("ln", "ln(v instant-vector)"),
+    ("log10", "log10(v instant-vector)"),
+    ("log2", "log2(v instant-vector)"),
+    ("mad_over_time", "mad_over_time(v range-vector)"),
+    ("max_over_time", "max_over_time(v range-vector)"),
+    ("min_over_time", "min_over_time(v range-vector)"),
+    ("minute", "minute(v=vector(time()) instant-vector)"),
+    ("month", "month(v=vector(time()) instant-vector)"),
+    ("predict_linear", "predict_linear(v range-vector, t scalar)"),
+    ("present_over_time", "present_over_time(v range-vector)"),
+    ("quantile_over_time", "quantile_over_time(φ scalar, v range-vector)"),
+    ("rad", "rad(v instant-vector)"),
+    ("rate", "rate(v range-vector)"),
+    ("resets", "resets(v range-vector)"),
+    ("round", "round(v instant-vector, to_nearest=1 scalar)"),
+    ("scalar", "scalar(v instant-vector)"),
+    ("sgn", "sgn(v instant-vector)"),
+    ("sin", "sin(v instant-vector)"),
+    ("sort", "sort(v instant-vector)"),
+    ("sort_desc", "sort_desc(v instant-vector)"),
+    ("sqrt", "sqrt(v instant-vector)"),
+    ("stddev_over_time", "stddev_over_time(v range-vector)"),
+    ("sum_over_time", "sum_over_time(v range-vector)"),
+    ("time", "time()"),
+    ("timestamp", "timestamp(v instant-vector)"),
+    ("vector", "vector(s scalar)"),
+];
+
+const AGGREGATIONS: &[(&str, &str)] = &[
+    ("sum", "sum over dimensions"),
+    ("min", "minimum over dimensions"),
+    ("max", "maximum over dimensions"),
+    ("avg", "average over dimensions"),
+    ("group", "all values in the resulting vector are 1"),
+    ("stddev", "population standard deviation over dimensions"),
+    ("stdvar", "population variance over dimensions"),
+    ("count", "count number of elements in the vector"),
+    ("count_values", "count number of elements with the same value"),
+    ("bottomk", "smallest k elements by sample value"),
+    ("topk", "largest k elements by sample value"),
+    ("quantile", "calculate φ-quantile (0 ≤ φ ≤ 1) over dimensions"),
+];
+
+const KEYWORDS: &[&str] = &["by", "without", "on", "ignoring", "group_left", "group_right", "offset", "bool", "and", "or", "unless"];
+
+// clauses whose parentheses hold label names rather than an expression
+const LABEL_LIST_CLAUSES: &[&str] = &["by", "without", "on", "ignoring", "group_left", "group_right"];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CompletionKind {
+    Metric,
+    Function,
+    Aggregation,
+    Keyword,
+    Label,
+    Value,
+}
+
+impl CompletionKind {
+    pub fn tag(&self) -> &'static str {
+        match self {
+            CompletionKind::Metric => "metric",
+            CompletionKind::Function => "func",
+            CompletionKind::Aggregation => "agg",
+            CompletionKind::Keyword => "kw",
+            CompletionKind::Label => "label",
+            CompletionKind::Value => "value",
+        }
+    }
+}
+
+pub struct Completion {
+    pub text: String,
+    pub kind: CompletionKind,
+    // metric type and help from /api/v1/metadata, or a function's signature
+    pub detail: String,
+}
+
+impl Completion {
+    // functions and aggregations open their argument list
+    pub fn insertion(&self) -> String {
+        match self.kind {
+            CompletionKind::Function | CompletionKind::Aggregation => format!("{}(", self.text),
+            _ => self.text.clone()
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Context {
+    // a metric name, function, aggregation or keyword
+    Expression,
+    // inside {…} or by (…); `metric` is the selector's metric name when there is one
+    LabelName { metric: Option<String> },
+    // inside the quotes of a label matcher
+    LabelValue { metric: Option<String>, label: String },
+}
+
+pub struct Request {
+    pub context: Context,
+    // byte offset where the word being completed starts
+    pub start: usize,
+}
+
+// what the metadata for a context is cached under, per server
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum CacheKey {
+    Metrics,
+    Metadata,
+    Labels(Option<String>),
+    Values(Option<String>, String),
+}
+
+pub enum CacheEntry {
+    Loading,
+    Names(Vec<String>),
+    Metadata(Metadata),
+    Failed(String),
+}
+
+#[derive(Default)]
+pub struct CompletionCache {
+    entries: HashMap<(String, CacheKey), CacheEntry>,
+}
+
+// The open completion popup in the query editor.
+pub struct Completions {
+    pub items: Vec<Completion>,
+    pub selected: usize,
+    pub start: usize,
+    pub loading: bool,
+    pub error: Option<String>,
+}
+
+pub fn is_word(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == ':'
+}
+
+// byte offset of the identifier that `text` ends with
+fn word_start(text: &str) -> usize {
+    text.char_indices()
+        .rev()
+        .find(|(_, c)| !is_word(*c))
+        .map(|(i, c)| i + c.len_utf8())
+        .unwrap_or(0)
+}
+
+// the identifier ending at `end`, skipping whitespace before it
+fn word_before(text: &str, end: usize) -> Option<String> {
+    let trimmed = text[..end].trim_end();
+    let start = word_start(trimmed);
+    match &trimmed[start..] {
+        "" => None,
+        w => Some(w.to_string())
+    }
+}
+
+// Works out what is being typed at the end of `before`, the text left of the cursor.
+pub fn request(before: &str) -> Option<Request> {
+    let mut string_start: Option<usize> = None;
+    let mut quote = '"';
+    let mut escaped = false;
+    let mut brace: Option<usize> = None;
+    let mut parens: Vec<usize> = vec![];
+    for (i, c) in before.char_indices() {
+        if string_start.is_some() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                _ if c == quote => string_start = None,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' | '\'' | '`' => {
+                quote = c;
+                string_start = Some(i + 1);
+            }
+            '{' => brace = Some(i),
+            '}' => brace = None,
+            '(' => parens.push(i),
+            ')' => { parens.pop(); }
+            _ => {}
+        }
+    }
+    let word_start = word_start(before);
+    match (string_start, brace) {
+        (Some(start), Some(open)) => {
+            // the matcher's label is the word before its =, !=, =~ or !~
+            let matcher = before[open + 1..start - 1].rsplit(',').next().unwrap_or("");
+            let label = matcher.trim_end().trim_end_matches(['=', '~', '!']).trim().to_string();
+            Some(Request { context: Context::LabelValue { metric: word_before(before, open), label }, start })
+        }
+        (Some(_), None) => None,
+        (None, Some(open)) => Some(Request { context: Context::LabelName { metric: word_before(before, open) }, start: word_start }),
+        (None, None) => {
+            let clause = parens.last().and_then(|p| word_before(before, *p));
+            match clause {
+                Some(w) if LABEL_LIST_CLAUSES.contains(&w.as_str()) => {
+                    Some(Request { context: Context::LabelName { metric: None }, start: word_start })
+                }
+                _ => Some(Request { context: Context::Expression, start: word_start })
+            }
+        }
+    }
+}
+
+impl CompletionCache {
+    // the cache entries a context draws from
+    pub fn keys(context: &Context) -> Vec<CacheKey> {
+        match context {
+            Context::Expression => vec![CacheKey::Metrics, CacheKey::Metadata],
+            Context::LabelName { metric } => vec![CacheKey::Labels(metric.clone())],
+            Context::LabelValue { metric, label } => vec![CacheKey::Values(metric.clone(), label.clone())],
+        }
+    }
+
+    pub fn contains(&self, server: &str, key: &CacheKey) -> bool {
+        self.entries.contains_key(&(server.to_string(), key.clone()))
+    }
+
+    pub fn insert(&mut self, server: &str, key: CacheKey, entry: CacheEntry) {
+        self.entries.insert((server.to_string(), key), entry);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn get(&self, server: &str, key: CacheKey) -> Option<&CacheEntry> {
+        self.entries.get(&(server.to_string(), key))
+    }
+
+    fn names(&self, server: &str, key: CacheKey) -> (&[String], bool) {
+        match self.get(server, key) {
+            Some(CacheEntry::Names(n)) => (n, false),
+            Some(CacheEntry::Loading) => (&[], true),
+            _ => (&[], false)
+        }
+    }
+
+    fn metric_detail(&self, server: &str, metric: &str) -> String {
+        match self.get(server, CacheKey::Metadata) {
+            Some(CacheEntry::Metadata(m)) => match m.get(metric).and_then(|v| v.first()) {
+                Some(meta) if meta.unit.is_empty() => format!("{}: {}", meta.kind, meta.help),
+                Some(meta) => format!("{} ({}): {}", meta.kind, meta.unit, meta.help),
+                None => "".to_string()
+            },
+            _ => "".to_string()
+        }
+    }
+
+    // the first failure among a context's entries, to show in place of an empty list
+    pub fn error(&self, server: &str, context: &Context) -> Option<&str> {
+        CompletionCache::keys(context).into_iter()
+            .find_map(|k| match self.get(server, k) {
+                Some(CacheEntry::Failed(e)) => Some(e.as_str()),
+                _ => None
+            })
+    }
+
+    // candidates starting with `prefix`, and whether some are still being fetched
+    pub fn candidates(&self, server: &str, context: &Context, prefix: &str) -> (Vec<Completion>, bool) {
+        let mut items = vec![];
+        let loading = match context {
+            Context::Expression => {
+                items.extend(AGGREGATIONS.iter()
+                    .filter(|(name, _)| name.starts_with(prefix))
+                    .map(|(name, help)| Completion { text: name.to_string(), kind: CompletionKind::Aggregation, detail: help.to_string() }));
+                items.extend(FUNCTIONS.iter()
+                    .filter(|(name, _)| name.starts_with(prefix))
+                    .map(|(name, sig)| Completion { text: name.to_string(), kind: CompletionKind::Function, detail: sig.to_string() }));
+                items.extend(KEYWORDS.iter()
+                    .filter(|k| !prefix.is_empty() && k.starts_with(prefix))
+                    .map(|k| Completion { text: k.to_string(), kind: CompletionKind::Keyword, detail: "".to_string() }));
+                let (metrics, loading) = self.names(server, CacheKey::Metrics);
+                items.extend(metrics.iter()
+                    .filter(|m| m.starts_with(prefix))
+                    .map(|m| Completion { text: m.clone(), kind: CompletionKind::Metric, detail: self.metric_detail(server, m) }));
+                loading
+            }
+            Context::LabelName { metric } => {
+                let (labels, loading) = self.names(server, CacheKey::Labels(metric.clone()));
+                items.extend(labels.iter()
+                    .filter(|l| l.as_str() != "__name__" && l.starts_with(prefix))
+                    .map(|l| Completion { text: l.clone(), kind: CompletionKind::Label, detail: "".to_string() }));
+                loading
+            }
+            Context::LabelValue { metric, label } => {
+                let (values, loading) = self.names(server, CacheKey::Values(metric.clone(), label.clone()));
+                items.extend(values.iter()
+                    .filter(|v| v.starts_with(prefix))
+                    .map(|v| Completion { text: v.replace('\\', "\\\\").replace('"', "\\\""), kind: CompletionKind::Value, detail: "".to_string() }));
+                loading
+            }
+        };
+        items.truncate(MAX_COMPLETIONS);
+        (items, loading)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(before: &str) -> Option<(Context, usize)> {
+        request(before).map(|r| (r.context, r.start))
+    }
+
+    fn label_value(metric: Option<&str>, label: &str) -> Context {
+        Context::LabelValue { metric: metric.map(String::from), label: label.to_string() }
+    }
+
+    #[test]
+    fn expressions() {
+        assert_eq!(context(""), Some((Context::Expression, 0)));
+        assert_eq!(context("rate(http_req"), Some((Context::Expression, 5)));
+        assert_eq!(context("sum(rate(x[5m])) / "), Some((Context::Expression, 19)));
+    }
+
+    #[test]
+    fn label_names() {
+        assert_eq!(context("up{jo"), Some((Context::LabelName { metric: Some("up".to_string()) }, 3)));
+        assert_eq!(context("{jo"), Some((Context::LabelName { metric: None }, 1)));
+        assert_eq!(context(r#"up{job="a", in"#), Some((Context::LabelName { metric: Some("up".to_string()) }, 12)));
+        assert_eq!(context("sum by ("), Some((Context::LabelName { metric: None }, 8)));
+        assert_eq!(context("sum(x) without (in"), Some((Context::LabelName { metric: None }, 16)));
+    }
+
+    #[test]
+    fn label_values() {
+        assert_eq!(context(r#"up{job="ap"#), Some((label_value(Some("up"), "job"), 8)));
+        assert_eq!(context(r#"up{env="x", job=~"api-v1."#), Some((label_value(Some("up"), "job"), 18)));
+        assert_eq!(context(r#"{job!='a\'b"#), Some((label_value(None, "job"), 7)));
+    }
+
+    #[test]
+    fn strings_outside_selectors_have_no_completions() {
+        assert!(request(r#"label_replace(up, "dst"#).is_none());
+    }
+
+    #[test]
+    fn candidates_filter_by_prefix_and_escape_values() {
+        let mut cache = CompletionCache::default();
+        let context = label_value(Some("up"), "job");
+        cache.insert("s", CacheKey::Values(Some("up".to_string()), "job".to_string()),
+                     CacheEntry::Names(vec!["api-v1".to_string(), "web".to_string(), "a\"b".to_string()]));
+        let (items, loading) = cache.candidates("s", &context, "a");
+        let texts: Vec<&str> = items.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(texts, vec!["api-v1", "a\\\"b"]);
+        assert!(!loading);
+
+        cache.insert("s", CacheKey::Labels(None), CacheEntry::Loading);
+        let (items, loading) = cache.candidates("s", &Context::LabelName { metric: None }, "");
+        assert!(items.is_empty());
+        assert!(loading);
+    }
+}
@@ -2,4 +2,42 @@ pub const DEFAULT_LOG_RING_BUFFER_SIZE: usize = 2;
 pub const DEFAULT_TICK_INTERVAL_MSECS: u64 = 250;
 pub const DEFAULT_SCREEN_MARGIN: u16 = 0;
 pub const MAX_RETAINED_LOG_LINES: usize= 10;
-pub const MINIMUM_SERVER_WAIT_SECS: u64 = 15;
\ No newline at end of file
+pub const MINIMUM_SERVER_WAIT_SECS: u64 = 15;
+pub const DEFAULT_SERVER_NAME: &str = "default";
+pub const EXIT_CODE_OK: i32 = 0;
+pub const EXIT_CODE_NO_DATA: i32 = 1;
+pub const EXIT_CODE_ERROR: i32 = 2;
+pub const DEFAULT_RANGE_START: &str = "now-1h";
+pub const DEFAULT_RANGE_END: &str = "now";
+pub const DEFAULT_RANGE_POINTS: u32 = 240;
+pub const DEFAULT_TUI_RANGE_SECS: f64 = 3600.0;
+pub const CHART_Y_PADDING_RATIO: f64 = 0.1;
+pub const CHART_X_LABEL_COUNT: usize = 5;
+pub const CHART_Y_LABEL_COUNT: usize = 3;
+pub const MIN_TUI_RANGE_SECS: f64 = 60.0;
+pub const MAX_TUI_RANGE_SECS: f64 = 90.0 * 86400.0;
+pub const TUI_PAN_RATIO: f64 = 0.5;
+pub const TUI_RANGE_PRESETS: [&str; 6] = ["5m", "15m", "1h", "6h", "24h", "7d"];
+pub const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 30;
+pub const ALL_OPTION: &str = "All";
+pub const DEFAULT_ALL_VALUE: &str = ".*";
+pub const CONFIG_PATH_ENV_VAR: &str = "CLIFANA_CONFIG";
+pub const CONFIG_DIR_NAME: &str = "clifana";
+pub const SYSTEM_CONFIG_PATH: &str = "/etc/clifana/config.toml";
+pub const USER_CONFIG_FILE_NAME: &str = "config.toml";
+pub const PROJECT_CONFIG_FILE_NAME: &str = "clifana.toml";
+pub const LEGACY_CONFIG_FILE_NAME: &str = "config.toml";
+pub const CONFIG_POLL_INTERVAL_SECS: u64 = 2;
+pub const HISTORY_FILE_NAME: &str = "history";
+pub const HISTORY_LIMIT: usize = 1000;
+pub const MAX_COMPLETIONS: usize = 200;
+pub const COMPLETION_POPUP_HEIGHT: u16 = 12;
+pub const DEFAULT_BAR_LIMIT: usize = 10;
+pub const DEFAULT_GAUGE_MAX: f64 = 100.0;
+pub const BAR_SCALE: u64 = 1000;
+pub const BAR_MAX_WIDTH: u16 = 9;
+pub const SPARKLINE_SCALE: u64 = 8;
+pub const HEATMAP_LEGEND_WIDTH: usize = 10;
+pub const MAX_AUTO_DECIMALS: i32 = 9;
+pub const ALERTS_REFRESH_SECS: u64 = 30;
+pub const ALERTS_PAGE_ROWS: usize = 5;
@@ -0,0 +1,252 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use crate::cfg_file::xdg_dir;
+use crate::completion::Completions;
+use crate::consts::*;
+
+pub enum EditorMode {
+    Edit,
+    // Ctrl-R: the search term and the history entry it currently matches
+    Search { term: String, hit: Option<usize> },
+    // Ctrl-S: the name to save the expression under
+    Save { name: String },
+}
+
+// State of the query input bar; the text itself lives in AppData.query.
+pub struct Editor {
+    // byte offset into the text, always on a char boundary
+    pub cursor: usize,
+    pub mode: EditorMode,
+    history: Vec<String>,
+    // Some while browsing history with ↑/↓, with the text being edited kept in `draft`
+    history_pos: Option<usize>,
+    draft: String,
+    // the Tab completion popup, while it is open
+    pub completion: Option<Completions>,
+}
+
+pub fn history_path() -> Option<PathBuf> {
+    xdg_dir("XDG_DATA_HOME", ".local/share").map(|d| d.join(CONFIG_DIR_NAME).join(HISTORY_FILE_NAME))
+}
+
+// one JSON string per line, so multi-line expressions survive the round trip
+fn load_history() -> Vec<String> {
+    let path = match history_path() {
+        Some(p) => p,
+        None => return vec![]
+    };
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(c) => c,
+        Err(_) => return vec![]
+    };
+    let mut history: Vec<String> = contents.lines()
+        .filter_map(|l| serde_json::from_str(l).ok())
+        .collect();
+    if history.len() > HISTORY_LIMIT {
+        history.drain(..history.len() - HISTORY_LIMIT);
+        // the file is only ever appended to, so trim it back down now and then
+        let lines: Vec<String> = history.iter().filter_map(|h| serde_json::to_string(h).ok()).collect();
+        if let Err(e) = std::fs::write(&path, lines.join("\n") + "\n") {
+            warn!("Unable to rewrite query history {}: {}", path.display(), e);
+        }
+    }
+    history
+}
+
+fn append_history(entry: &str) -> std::io::Result<()> {
+    let path = match history_path() {
+        Some(p) => p,
+        None => return Ok(())
+    };
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)
+}
+
+// the most recent entry before `before` containing `term`
+fn search_history(history: &[String], term: &str, before: usize) -> Option<usize> {
+    history[..before.min(history.len())].iter().rposition(|h| h.contains(term))
+}
+
+impl Editor {
+    pub fn new(text: &str) -> Self {
+        Editor {
+            cursor: text.len(),
+            mode: EditorMode::Edit,
+            history: load_history(),
+            history_pos: None,
+            draft: String::new(),
+            completion: None,
+        }
+    }
+
+    pub fn insert(&mut self, text: &mut String, c: char) {
+        match &mut self.mode {
+            EditorMode::Edit => {
+                text.insert(self.cursor, c);
+                self.cursor += c.len_utf8();
+            }
+            EditorMode::Search { term, hit } if !c.is_control() => {
+                term.push(c);
+                *hit = search_history(&self.history, term, self.history.len());
+            }
+            EditorMode::Save { name } if !c.is_control() => name.push(c),
+            _ => {}
+        }
+    }
+
+    pub fn backspace(&mut self, text: &mut String) {
+        match &mut self.mode {
+            EditorMode::Edit => {
+                if let Some(c) = text[..self.cursor].chars().next_back() {
+                    self.cursor -= c.len_utf8();
+                    text.remove(self.cursor);
+                }
+            }
+            EditorMode::Search { term, hit } => {
+                term.pop();
+                *hit = search_history(&self.history, term, self.history.len());
+            }
+            EditorMode::Save { name } => { name.pop(); }
+        }
+    }
+
+    pub fn delete(&mut self, text: &mut String) {
+        if let (EditorMode::Edit, true) = (&self.mode, self.cursor < text.len()) {
+            text.remove(self.cursor);
+        }
+    }
+
+    // Ctrl-U: deletes from the start of the line up to the cursor
+    pub fn kill_line(&mut self, text: &mut String) {
+        if let EditorMode::Edit = self.mode {
+            let start = line_start(text, self.cursor);
+            text.replace_range(start..self.cursor, "");
+            self.cursor = start;
+        }
+    }
+
+    pub fn left(&mut self, text: &str) {
+        if let Some(c) = text[..self.cursor].chars().next_back() {
+            self.cursor -= c.len_utf8();
+        }
+    }
+
+    pub fn right(&mut self, text: &str) {
+        if let Some(c) = text[self.cursor..].chars().next() {
+            self.cursor += c.len_utf8();
+        }
+    }
+
+    pub fn home(&mut self, text: &str) {
+        self.cursor = line_start(text, self.cursor);
+    }
+
+    pub fn end(&mut self, text: &str) {
+        self.cursor = line_end(text, self.cursor);
+    }
+
+    // moves between lines of a multi-line expression, and through history from the first line
+    pub fn up(&mut self, text: &mut String) {
+        let start = line_start(text, self.cursor);
+        if start == 0 {
+            let pos = self.history_pos.unwrap_or(self.history.len());
+            if pos > 0 {
+                self.show_history(text, Some(pos - 1));
+            }
+            return;
+        }
+        let prev_start = line_start(text, start - 1);
+        self.cursor = column_offset(text, prev_start, start - 1, column(text, self.cursor));
+    }
+
+    pub fn down(&mut self, text: &mut String) {
+        let end = line_end(text, self.cursor);
+        if end == text.len() {
+            match self.history_pos {
+                Some(pos) if pos + 1 < self.history.len() => self.show_history(text, Some(pos + 1)),
+                Some(_) => self.show_history(text, None),
+                None => {}
+            }
+            return;
+        }
+        let next_end = line_end(text, end + 1);
+        self.cursor = column_offset(text, end + 1, next_end, column(text, self.cursor));
+    }
+
+    fn show_history(&mut self, text: &mut String, pos: Option<usize>) {
+        if self.history_pos.is_none() {
+            self.draft = text.clone();
+        }
+        *text = match pos {
+            Some(p) => self.history[p].clone(),
+            None => std::mem::take(&mut self.draft)
+        };
+        self.history_pos = pos;
+        self.cursor = text.len();
+    }
+
+    // Ctrl-R starts a reverse search, and pressed again steps to the next older match
+    pub fn search(&mut self) {
+        self.mode = match std::mem::replace(&mut self.mode, EditorMode::Edit) {
+            EditorMode::Search { term, hit } => {
+                let before = hit.unwrap_or(self.history.len());
+                let older = search_history(&self.history, &term, before).or(hit);
+                EditorMode::Search { term, hit: older }
+            }
+            _ => EditorMode::Search { term: String::new(), hit: None }
+        };
+    }
+
+    pub fn search_hit(&self) -> Option<&str> {
+        match &self.mode {
+            EditorMode::Search { hit: Some(h), .. } => self.history.get(*h).map(|h| h.as_str()),
+            _ => None
+        }
+    }
+
+    // replaces the text with the current search match and goes back to editing
+    pub fn accept_search(&mut self, text: &mut String) {
+        if let Some(hit) = self.search_hit() {
+            *text = hit.to_string();
+            self.cursor = text.len();
+        }
+        self.mode = EditorMode::Edit;
+    }
+
+    pub fn record(&mut self, entry: &str) {
+        self.history_pos = None;
+        if entry.is_empty() || self.history.last().map(|h| h.as_str()) == Some(entry) {
+            return;
+        }
+        self.history.push(entry.to_string());
+        if let Err(e) = append_history(entry) {
+            warn!("Unable to save query history: {}", e);
+        }
+    }
+
+    // the cursor's line and column, counted in chars, for placing the terminal cursor
+    pub fn position(&self, text: &str) -> (usize, usize) {
+        (text[..self.cursor].matches('\n').count(), column(text, self.cursor))
+    }
+}
+
+fn line_start(text: &str, at: usize) -> usize {
+    text[..at].rfind('\n').map(|i| i + 1).unwrap_or(0)
+}
+
+fn line_end(text: &str, at: usize) -> usize {
+    text[at..].find('\n').map(|i| at + i).unwrap_or(text.len())
+}
+
+fn column(text: &str, at: usize) -> usize {
+    text[line_start(text, at)..at].chars().count()
+}
+
+// the byte offset `col` chars into the line spanning start..end, clamped to its end
+fn column_offset(text: &str, start: usize, end: usize, col: usize) -> usize {
+    text[start..end].char_indices().nth(col).map(|(i, _)| start + i).unwrap_or(end)
+}
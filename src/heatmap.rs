@@ -0,0 +1,200 @@
+use std::collections::{BTreeMap, BTreeSet};
+use crate::prometheus::{parse_sample_value, QueryData, RangeSeries};
+
+// A histogram over time: one row per bucket, lowest first, holding how many observations fell into that
+// bucket (not the cumulative count prometheus reports for classic histograms) at each sample time.
+pub struct Heatmap {
+    pub lower: Vec<f64>,
+    pub upper: Vec<f64>,
+    pub times: Vec<f64>,
+    // counts[time][bucket]
+    pub counts: Vec<Vec<f64>>,
+}
+
+// sample times as map keys; range query timestamps are whole milliseconds at most
+fn time_key(t: f64) -> i64 {
+    (t * 1000.0).round() as i64
+}
+
+impl Heatmap {
+    pub fn from_data(data: &QueryData) -> Result<Heatmap, String> {
+        let series = match data {
+            QueryData::Matrix(m) => m,
+            other => return Err(format!("A heatmap needs a range query, but the result was a {}", other.result_type()))
+        };
+        match series.iter().any(|s| !s.histograms.is_empty()) {
+            true => Ok(from_native(series)),
+            false => from_buckets(series)
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.times.is_empty() || self.upper.is_empty()
+    }
+
+    // histogram_quantile's estimate for one set of bucket counts, interpolating linearly inside the bucket
+    // the rank falls in. Returns the bucket position (index plus the fraction through it) and the value.
+    pub fn quantile(&self, counts: &[f64], q: f64) -> Option<(f64, f64)> {
+        let total: f64 = counts.iter().sum();
+        if total <= 0.0 {
+            return None;
+        }
+        let rank = q * total;
+        let mut seen = 0.0;
+        for (b, count) in counts.iter().enumerate() {
+            if *count > 0.0 && seen + count >= rank {
+                let fraction = (rank - seen) / count;
+                let (lower, upper) = (self.lower[b], self.upper[b]);
+                // like prometheus, a rank in the +Inf bucket reports that bucket's lower bound
+                let value = match upper.is_infinite() {
+                    true => lower,
+                    false => lower + (upper - lower) * fraction
+                };
+                return Some((b as f64 + fraction, value));
+            }
+            seen += count;
+        }
+        None
+    }
+}
+
+// classic histograms: cumulative series with an `le` label, summed across any other labels
+fn from_buckets(series: &[RangeSeries]) -> Result<Heatmap, String> {
+    let mut buckets: Vec<(f64, BTreeMap<i64, f64>)> = vec![];
+    for s in series {
+        let le = match s.metric.get("le").map(|le| parse_sample_value(le)) {
+            Some(le) if !le.is_nan() => le,
+            _ => continue
+        };
+        let index = match buckets.iter().position(|(b, _)| *b == le) {
+            Some(i) => i,
+            None => {
+                buckets.push((le, BTreeMap::new()));
+                buckets.len() - 1
+            }
+        };
+        for v in s.values.iter().filter(|v| v.value().is_finite()) {
+            *buckets[index].1.entry(time_key(v.timestamp())).or_default() += v.value();
+        }
+    }
+    if buckets.is_empty() {
+        return Err("A heatmap needs series with an le label, such as sum by (le) (rate(x_bucket[5m])), or native histograms".to_string());
+    }
+    buckets.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let upper: Vec<f64> = buckets.iter().map(|(le, _)| *le).collect();
+    // the lowest bucket is taken to start at zero, as histogram_quantile does
+    let lower: Vec<f64> = upper.iter()
+        .enumerate()
+        .map(|(i, le)| match i {
+            0 => le.min(0.0),
+            _ => upper[i - 1]
+        })
+        .collect();
+    let times: BTreeSet<i64> = buckets.iter().flat_map(|(_, values)| values.keys().copied()).collect();
+    let counts = times.iter()
+        .map(|t| {
+            let mut below = 0.0;
+            buckets.iter()
+                .map(|(_, values)| {
+                    let cumulative = values.get(t).copied().unwrap_or(below);
+                    // scrapes landing between buckets can make a higher bucket briefly smaller
+                    let count = (cumulative - below).max(0.0);
+                    below = cumulative.max(below);
+                    count
+                })
+                .collect()
+        })
+        .collect();
+    Ok(Heatmap { lower, upper, times: times.iter().map(|t| *t as f64 / 1000.0).collect(), counts })
+}
+
+// native histograms: every distinct bucket across all samples becomes a row
+fn from_native(series: &[RangeSeries]) -> Heatmap {
+    let mut rows: Vec<(f64, f64)> = series.iter()
+        .flat_map(|s| s.histograms.iter())
+        .flat_map(|h| h.1.buckets.iter().map(|b| (b.lower(), b.upper())))
+        .filter(|(lower, upper)| !lower.is_nan() && !upper.is_nan())
+        .collect();
+    rows.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.total_cmp(&b.0)));
+    rows.dedup();
+
+    let mut samples: BTreeMap<i64, Vec<f64>> = BTreeMap::new();
+    for h in series.iter().flat_map(|s| s.histograms.iter()) {
+        let counts = samples.entry(time_key(h.timestamp())).or_insert_with(|| vec![0.0; rows.len()]);
+        for b in &h.1.buckets {
+            let row = rows.binary_search_by(|r| r.1.total_cmp(&b.upper()).then(r.0.total_cmp(&b.lower())));
+            if let (Ok(row), true) = (row, b.count().is_finite()) {
+                counts[row] += b.count();
+            }
+        }
+    }
+    Heatmap {
+        lower: rows.iter().map(|r| r.0).collect(),
+        upper: rows.iter().map(|r| r.1).collect(),
+        times: samples.keys().map(|t| *t as f64 / 1000.0).collect(),
+        counts: samples.into_values().collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn matrix(result: serde_json::Value) -> QueryData {
+        serde_json::from_value(json!({"resultType": "matrix", "result": result})).unwrap()
+    }
+
+    fn bucket(le: &str, pod: &str, values: &[(f64, &str)]) -> serde_json::Value {
+        json!({"metric": {"le": le, "pod": pod}, "values": values})
+    }
+
+    #[test]
+    fn classic_buckets_are_summed_and_decumulated() {
+        let data = matrix(json!([
+            bucket("+Inf", "a", &[(1.0, "4"), (2.0, "8")]),
+            bucket("0.1", "a", &[(1.0, "1"), (2.0, "2")]),
+            bucket("1", "a", &[(1.0, "3"), (2.0, "6")]),
+            bucket("0.1", "b", &[(1.0, "1"), (2.0, "1")]),
+            bucket("1", "b", &[(1.0, "1"), (2.0, "1")]),
+            bucket("+Inf", "b", &[(1.0, "2"), (2.0, "1")]),
+        ]));
+        let heatmap = Heatmap::from_data(&data).unwrap();
+        assert_eq!(heatmap.upper, vec![0.1, 1.0, f64::INFINITY]);
+        assert_eq!(heatmap.lower, vec![0.0, 0.1, 1.0]);
+        assert_eq!(heatmap.times, vec![1.0, 2.0]);
+        assert_eq!(heatmap.counts[0], vec![2.0, 2.0, 2.0]);
+        // pod b's +Inf bucket dipping below its 1 bucket doesn't make a negative count
+        assert_eq!(heatmap.counts[1], vec![3.0, 4.0, 2.0]);
+    }
+
+    #[test]
+    fn series_without_buckets_are_errors() {
+        let data = matrix(json!([{"metric": {"pod": "a"}, "values": [[1.0, "1"]]}]));
+        assert!(Heatmap::from_data(&data).is_err());
+        let data: QueryData = serde_json::from_value(json!({"resultType": "scalar", "result": [1.0, "1"]})).unwrap();
+        assert!(Heatmap::from_data(&data).is_err());
+    }
+
+    #[test]
+    fn native_histograms_become_rows() {
+        let data = matrix(json!([{"metric": {}, "histograms": [
+            [1.0, {"count": "3", "sum": "1", "buckets": [[0, "0", "1", "1"], [0, "1", "2", "2"]]}],
+            [2.0, {"count": "1", "sum": "1", "buckets": [[0, "1", "2", "1"]]}],
+        ]}]));
+        let heatmap = Heatmap::from_data(&data).unwrap();
+        assert_eq!(heatmap.lower, vec![0.0, 1.0]);
+        assert_eq!(heatmap.upper, vec![1.0, 2.0]);
+        assert_eq!(heatmap.counts, vec![vec![1.0, 2.0], vec![0.0, 1.0]]);
+    }
+
+    #[test]
+    fn quantiles_interpolate_within_buckets() {
+        let heatmap = Heatmap { lower: vec![0.0, 0.1, 1.0], upper: vec![0.1, 1.0, f64::INFINITY], times: vec![], counts: vec![] };
+        assert_eq!(heatmap.quantile(&[2.0, 2.0, 2.0], 0.5), Some((1.5, 0.55)));
+        // the +Inf bucket reports its lower bound
+        assert_eq!(heatmap.quantile(&[2.0, 2.0, 2.0], 1.0), Some((3.0, 1.0)));
+        assert_eq!(heatmap.quantile(&[0.0, 0.0, 0.0], 0.5), None);
+    }
+}
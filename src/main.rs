@@ -1,24 +1,34 @@
-#[macro_use]
 extern crate tokio;
 
 mod cli;
 mod cfg_file;
 mod query;
 mod prometheus;
+mod client;
 mod app_data;
+mod panel;
+mod variables;
 mod consts;
 mod ui;
+mod timespec;
+mod output;
+mod check;
+mod editor;
+mod completion;
+mod heatmap;
+mod alerts;
+mod units;
 
 use std::panic::catch_unwind;
 use std::cmp::Ordering;
 use clap::Parser;
-use cli::Cli;
-use cfg_file::ConfigFile;
-use query::execute_query;
+use cli::{Cli, Commands, ConfigCommands, Tui};
+use cfg_file::{ConfigFile, ConfigSearch};
+use query::{execute_query, execute_query_range, parse_eval_args};
 
 use crossterm::{
     cursor,
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
+    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyModifiers, MouseEventKind},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
@@ -37,7 +47,10 @@ use std::ops::Add;
 use std::time::SystemTime;
 use log::LevelFilter;
 use app_data::AppData;
+use editor::Editor;
+use panel::QueryTarget;
 use crate::ui::ui;
+use crate::consts::*;
 
 
 #[macro_use]
@@ -54,7 +67,18 @@ async fn main() -> anyhow::Result<()> {
 
     let cli = Cli::parse();
 
-    let mut app = AppData::new(cli.config);
+    if let Some(Commands::Config(ConfigCommands::Path)) = &cli.command {
+        print_config_paths(&ConfigSearch::new(cli.config.clone()));
+        std::process::exit(EXIT_CODE_OK);
+    }
+    let config = match ConfigFile::new(cli.config.clone()) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(EXIT_CODE_ERROR);
+        }
+    };
+    let mut app = AppData::new(config);
 
     let mut log_level_int = match cli.debug.cmp(&app.config.log_level) {
         Ordering::Greater => cli.debug,
@@ -90,17 +114,36 @@ async fn main() -> anyhow::Result<()> {
             log_buffer.lock().unwrap().push_front(format!("{}", record.args()));
         }))
         .apply().unwrap();
+
+    let tui_args = match &cli.command {
+        Some(Commands::Query(args)) => exit_with(execute_query(&app.config, args).await),
+        Some(Commands::Range(args)) => exit_with(execute_query_range(&app.config, args).await),
+        Some(Commands::Tui(args)) => Some(args),
+        Some(Commands::Config(ConfigCommands::Check(args))) => {
+            let exit_code = match check::check_config(&app.config, args).await {
+                0 => EXIT_CODE_OK,
+                _ => EXIT_CODE_ERROR
+            };
+            std::process::exit(exit_code);
+        }
+        Some(Commands::Config(ConfigCommands::Path)) | None => None
+    };
+    let default_args = Tui::default();
+    let tui_args = tui_args.unwrap_or(&default_args);
+    match &tui_args.dashboard {
+        Some(name) => app.set_dashboard(name, &parse_eval_args(&tui_args.eval)?),
+        None => app.set_target(QueryTarget::from_args(tui_args)?)
+    }
+
+    #[cfg(unix)]
+    app.reload_on_sighup();
+
     enable_raw_mode()?;
     let mut stdout = io::stdout();
     execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
-/*    match &cli.command {
-        crate::cli::Commands::Query(args) => {
-            execute_query(&app.config, args)?;
-        }
-    };
-*/    let tick_rate = Duration::from_millis(app.tick_interval_msecs);
+    let tick_rate = Duration::from_millis(app.tick_interval_msecs);
     let res = run_app(&mut terminal, app, tick_rate).await;
     if res.is_err() {
         restore_terminal(Some(format!("{:#?}", res)));
@@ -109,6 +152,27 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+// maps the outcome of a one-shot CLI command onto the process exit status
+fn exit_with(result: anyhow::Result<usize>) -> ! {
+    let exit_code = match result {
+        Ok(0) => EXIT_CODE_NO_DATA,
+        Ok(_) => EXIT_CODE_OK,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            EXIT_CODE_ERROR
+        }
+    };
+    std::process::exit(exit_code);
+}
+
+fn print_config_paths(search: &ConfigSearch) {
+    let found = search.found();
+    for path in search.candidates() {
+        let status = if found.contains(&path) { "loaded" } else { "not found" };
+        println!("{}\t{}", path.display(), status);
+    }
+}
+
 fn restore_terminal(err: Option<String>) {
     // clean up
     let mut stdout = io::stdout();
@@ -141,17 +205,105 @@ async fn run_app<B: Backend>(
             .checked_sub(last_tick.elapsed())
             .unwrap_or_else(|| Duration::from_secs(0));
         if crossterm::event::poll(timeout)? {
-            if let Event::Key(key) = event::read()? {
-                match key.code {
+            match event::read()? {
+                Event::Key(key) if app.completing() => match key.code {
+                    KeyCode::Up => app.completion_move(-1),
+                    KeyCode::Down => app.completion_move(1),
+                    KeyCode::Tab | KeyCode::Enter => app.accept_completion(),
+                    KeyCode::Esc => app.close_completion(),
+                    KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                        if app.ends_completion(c) {
+                            app.close_completion();
+                        }
+                        app.edit(|e, q| e.insert(q, c))
+                    }
+                    KeyCode::Backspace => app.edit(Editor::backspace),
+                    _ => app.close_completion()
+                },
+                Event::Key(key) if app.editor.is_some() => match (key.code, key.modifiers) {
+                    (KeyCode::Tab, _) => app.editor_complete(),
+                    (KeyCode::Char('r'), KeyModifiers::CONTROL) => app.edit(|e, _| e.search()),
+                    (KeyCode::Char('s'), KeyModifiers::CONTROL) => app.editor_save(),
+                    (KeyCode::Char('u'), KeyModifiers::CONTROL) => app.edit(Editor::kill_line),
+                    // terminals can't report Shift-Enter, so Alt-Enter and Ctrl-J start a new line
+                    (KeyCode::Char('j'), KeyModifiers::CONTROL) | (KeyCode::Enter, KeyModifiers::ALT) => {
+                        app.edit(|e, q| e.insert(q, '\n'))
+                    }
+                    (KeyCode::Enter, _) => app.editor_enter(),
+                    (KeyCode::Esc, _) => app.editor_escape(),
+                    (KeyCode::Char(c), m) if !m.contains(KeyModifiers::CONTROL) => app.edit(|e, q| e.insert(q, c)),
+                    (KeyCode::Backspace, _) => app.edit(Editor::backspace),
+                    (KeyCode::Delete, _) => app.edit(Editor::delete),
+                    (KeyCode::Left, _) => app.edit(|e, q| e.left(q)),
+                    (KeyCode::Right, _) => app.edit(|e, q| e.right(q)),
+                    (KeyCode::Home, _) => app.edit(|e, q| e.home(q)),
+                    (KeyCode::End, _) => app.edit(|e, q| e.end(q)),
+                    (KeyCode::Up, _) => app.edit(Editor::up),
+                    (KeyCode::Down, _) => app.edit(Editor::down),
+                    _ => {}
+                },
+                Event::Key(key) if app.picker.is_some() => match key.code {
+                    KeyCode::Left => app.picker_move(-1, 0),
+                    KeyCode::Right | KeyCode::Tab => app.picker_move(1, 0),
+                    KeyCode::Up => app.picker_move(0, -1),
+                    KeyCode::Down => app.picker_move(0, 1),
+                    KeyCode::Char(' ') | KeyCode::Enter => app.picker_toggle(),
+                    KeyCode::Esc | KeyCode::Char('v') => app.picker = None,
+                    _ => {}
+                },
+                Event::Key(key) if app.alerts_view.is_some() => match key.code {
+                    KeyCode::Up => app.alerts_move(-1),
+                    KeyCode::Down => app.alerts_move(1),
+                    KeyCode::PageUp => app.alerts_move(-(ALERTS_PAGE_ROWS as isize)),
+                    KeyCode::PageDown => app.alerts_move(ALERTS_PAGE_ROWS as isize),
+                    KeyCode::Esc | KeyCode::Char('a') => app.alerts_view = None,
+                    KeyCode::Char('q') => { return Ok(()); }
+                    _ => {}
+                },
+                Event::Key(key) if app.menu_active => match key.code {
                     KeyCode::Left => app.menu.left(),
                     KeyCode::Right => app.menu.right(),
                     KeyCode::Up => app.menu.up(),
                     KeyCode::Down => app.menu.down(),
-                    KeyCode::Esc => app.menu.reset(),
                     KeyCode::Enter => app.menu.select(),
+                    KeyCode::Esc | KeyCode::F(10) => {
+                        app.menu.reset();
+                        app.menu_active = false;
+                    }
                     KeyCode::Char('q') => { return Ok(()); }
                     _ => {}
-                }
+                },
+                Event::Key(key) => match key.code {
+                    KeyCode::F(10) | KeyCode::Char('m') => {
+                        app.menu.activate();
+                        app.menu_active = true;
+                    }
+                    KeyCode::Char('v') => app.open_picker(),
+                    KeyCode::Char('a') => app.toggle_alerts(),
+                    KeyCode::Char('/') | KeyCode::Char(':') => app.open_editor(),
+                    KeyCode::Tab => app.focus_next(),
+                    KeyCode::BackTab => app.focus_prev(),
+                    KeyCode::Left => app.cursor_left(),
+                    KeyCode::Right => app.cursor_right(),
+                    KeyCode::Esc => app.cursor = None,
+                    KeyCode::Char('q') => { return Ok(()); }
+                    KeyCode::Char('+') | KeyCode::Char('=') => app.zoom_in(),
+                    KeyCode::Char('-') => app.zoom_out(),
+                    KeyCode::Char('[') => app.pan_back(),
+                    KeyCode::Char(']') => app.pan_forward(),
+                    KeyCode::Char('n') => app.jump_to_now(),
+                    KeyCode::Char('r') => app.request_reload(),
+                    KeyCode::Char('s') => app.cycle_sort(),
+                    KeyCode::Char('S') => app.reverse_sort(),
+                    _ => {}
+                },
+                Event::Mouse(mouse) => match mouse.kind {
+                    MouseEventKind::Moved | MouseEventKind::Down(_) | MouseEventKind::Drag(_) => {
+                        app.cursor_at_column(mouse.column, mouse.row)
+                    }
+                    _ => {}
+                },
+                _ => {}
             }
         };
         //endregion
@@ -163,6 +315,27 @@ async fn run_app<B: Backend>(
                     "exit" => {
                         return Ok(());
                     }
+                    "alerts" => {
+                        app.toggle_alerts();
+                        app.menu.reset();
+                        app.menu_active = false;
+                    }
+                    "reload" => {
+                        app.request_reload();
+                        app.menu.reset();
+                        app.menu_active = false;
+                    }
+                    preset if preset.starts_with("range:") => {
+                        match timespec::parse_duration(&preset["range:".len()..]) {
+                            Ok(d) => {
+                                app.jump_to_now();
+                                app.set_range(d.as_secs_f64());
+                            }
+                            Err(e) => warn!("{}", e)
+                        }
+                        app.menu.reset();
+                        app.menu_active = false;
+                    }
                     _ => {
                         // println!("{} selected", item);
                     }
@@ -173,7 +346,7 @@ async fn run_app<B: Backend>(
 
         //region application tick processing
         if last_tick.elapsed() >= tick_rate {
-            app.on_tick().await;
+            app.on_tick();
             last_tick = Instant::now();
         }
         //endregion
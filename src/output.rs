@@ -0,0 +1,164 @@
+use std::collections::BTreeSet;
+use std::io::Write;
+use clap::ValueEnum;
+use serde_json::json;
+use crate::prometheus::{parse_sample_value, HistogramPair, Labels, NativeHistogram, QueryData, SamplePair};
+use crate::units::ValueFormat;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Aligned human-readable table
+    Table,
+    /// The raw JSON response body from Prometheus
+    Json,
+    /// One JSON object per sample
+    Ndjson,
+    /// One row per sample with a column per label
+    Csv,
+    /// Like csv but tab separated
+    Tsv,
+    /// Prometheus text exposition format
+    Prom,
+}
+
+static EMPTY_LABELS: Labels = Labels::new();
+
+struct Row<'a> {
+    metric: &'a Labels,
+    timestamp: f64,
+    value: String,
+    histogram: Option<&'a NativeHistogram>,
+}
+
+impl<'a> Row<'a> {
+    fn sample(metric: &'a Labels, pair: &SamplePair) -> Self {
+        Row { metric, timestamp: pair.timestamp(), value: pair.1.clone(), histogram: None }
+    }
+
+    // native histograms are flattened to their observation count for tabular formats
+    fn histogram(metric: &'a Labels, pair: &'a HistogramPair) -> Self {
+        Row { metric, timestamp: pair.timestamp(), value: pair.1.count.clone(), histogram: Some(&pair.1) }
+    }
+}
+
+fn rows(data: &QueryData) -> Vec<Row<'_>> {
+    match data {
+        QueryData::Vector(v) => v.iter()
+            .flat_map(|s| s.value.iter().map(|p| Row::sample(&s.metric, p))
+                .chain(s.histogram.iter().map(|h| Row::histogram(&s.metric, h))))
+            .collect(),
+        QueryData::Matrix(m) => m.iter()
+            .flat_map(|s| s.values.iter().map(|p| Row::sample(&s.metric, p))
+                .chain(s.histograms.iter().map(|h| Row::histogram(&s.metric, h))))
+            .collect(),
+        QueryData::Scalar(p) | QueryData::String(p) => vec![Row::sample(&EMPTY_LABELS, p)],
+    }
+}
+
+fn label(metric: &Labels, name: &str) -> String {
+    metric.get(name).cloned().unwrap_or_default()
+}
+
+fn label_names(data: &QueryData) -> Vec<String> {
+    let names: BTreeSet<String> = match data {
+        QueryData::Vector(v) => v.iter().flat_map(|s| s.metric.keys().cloned()).collect(),
+        QueryData::Matrix(m) => m.iter().flat_map(|s| s.metric.keys().cloned()).collect(),
+        _ => BTreeSet::new()
+    };
+    names.into_iter().collect()
+}
+
+fn csv_escape(field: &str, sep: char) -> String {
+    if field.contains(sep) || field.contains('"') || field.contains('\n') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn prom_escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+// `units` only applies to the table; the other formats are for machines and keep prometheus' own value strings
+pub fn write_output<W: Write>(out: &mut W, format: OutputFormat, units: &ValueFormat, raw: &str, data: &QueryData) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Json => {
+            writeln!(out, "{}", raw.trim_end())?;
+        }
+        OutputFormat::Ndjson => {
+            for r in rows(data) {
+                let line = match r.histogram {
+                    Some(h) => json!({"metric": r.metric, "timestamp": r.timestamp, "histogram": h}),
+                    None => json!({"metric": r.metric, "timestamp": r.timestamp, "value": r.value})
+                };
+                writeln!(out, "{}", line)?;
+            }
+        }
+        OutputFormat::Csv | OutputFormat::Tsv => {
+            let sep = if format == OutputFormat::Csv { ',' } else { '\t' };
+            let names = label_names(data);
+            let header: Vec<String> = ["timestamp", "value"].iter().map(|s| s.to_string())
+                .chain(names.iter().cloned())
+                .collect();
+            writeln!(out, "{}", itertools::join(header.iter().map(|h| csv_escape(h, sep)), &sep.to_string()))?;
+            for r in rows(data) {
+                let fields: Vec<String> = [r.timestamp.to_string(), r.value.clone()].into_iter()
+                    .chain(names.iter().map(|n| label(r.metric, n)))
+                    .collect();
+                writeln!(out, "{}", itertools::join(fields.iter().map(|f| csv_escape(f, sep)), &sep.to_string()))?;
+            }
+        }
+        OutputFormat::Prom => {
+            for r in rows(data) {
+                let name = label(r.metric, "__name__");
+                let pairs = itertools::join(
+                    r.metric.iter()
+                        .filter(|(k, _)| k.as_str() != "__name__")
+                        .map(|(k, v)| format!("{}=\"{}\"", k, prom_escape(v))),
+                    ",");
+                let name = if name.is_empty() { "result".to_string() } else { name };
+                let ts = (r.timestamp * 1000.0).round() as i64;
+                match r.histogram {
+                    Some(h) => {
+                        writeln!(out, "{}_count{{{}}} {} {}", name, pairs, h.count, ts)?;
+                        writeln!(out, "{}_sum{{{}}} {} {}", name, pairs, h.sum, ts)?;
+                    }
+                    None => writeln!(out, "{}{{{}}} {} {}", name, pairs, r.value, ts)?
+                }
+            }
+        }
+        OutputFormat::Table => {
+            let names = label_names(data);
+            // a string result is text, not a number to format
+            let numeric = !matches!(data, QueryData::String(_));
+            let mut table: Vec<Vec<String>> = vec![
+                names.iter().cloned().chain(["timestamp".to_string(), "value".to_string()]).collect()
+            ];
+            for r in rows(data) {
+                table.push(names.iter().map(|n| label(r.metric, n))
+                    .chain([
+                        humantime::format_rfc3339_seconds(
+                            std::time::UNIX_EPOCH + std::time::Duration::from_secs_f64(r.timestamp.max(0.0))
+                        ).to_string(),
+                        match (r.histogram, numeric) {
+                            (None, true) => units.format(parse_sample_value(&r.value)),
+                            // an observation count, not a value in the query's unit, or a string result
+                            _ => r.value.clone()
+                        }
+                    ])
+                    .collect());
+            }
+            let widths: Vec<usize> = (0..table[0].len())
+                .map(|i| table.iter().map(|row| row[i].chars().count()).max().unwrap_or(0))
+                .collect();
+            for row in table {
+                let line = itertools::join(
+                    row.iter().zip(&widths).map(|(cell, w)| format!("{:<width$}", cell, width = w)),
+                    "  ");
+                writeln!(out, "{}", line.trim_end())?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,364 @@
+use std::collections::BTreeMap;
+use ratatui::layout::Rect;
+use ratatui::style::Color;
+use tokio::task::JoinHandle;
+use crate::cfg_file::{ConfigFile, DashboardRef, PanelKind, PanelRef, ThresholdRef};
+use crate::cli::Tui;
+use crate::client::{render_query, render_template, ClientError};
+use crate::heatmap::Heatmap;
+use crate::consts::*;
+use crate::prometheus::{format_labels, Labels, QueryData};
+use crate::query::parse_eval_args;
+use crate::timespec::now_secs;
+use crate::units::{Unit, ValueFormat};
+
+pub enum QuerySource {
+    Named(String),
+    Inline(String),
+}
+
+pub struct QueryTarget {
+    pub server: String,
+    pub source: Option<QuerySource>,
+    pub vars: BTreeMap<String, String>,
+    pub legend: Option<String>,
+    pub unit: Option<Unit>,
+    pub decimals: Option<usize>,
+}
+
+impl QueryTarget {
+    pub fn from_args(args: &Tui) -> anyhow::Result<Self> {
+        let source = match (&args.query, &args.expr) {
+            (_, Some(expr)) => Some(QuerySource::Inline(expr.clone())),
+            (Some(name), None) => Some(QuerySource::Named(name.clone())),
+            (None, None) => None
+        };
+        Ok(QueryTarget {
+            server: args.server.clone().unwrap_or_else(|| DEFAULT_SERVER_NAME.to_string()),
+            source,
+            vars: parse_eval_args(&args.eval)?,
+            legend: args.legend.clone(),
+            unit: args.unit,
+            decimals: args.decimals,
+        })
+    }
+
+    // panel server, else dashboard server, else the default server
+    pub fn from_panel(dashboard: &DashboardRef, panel: &PanelRef, vars: &BTreeMap<String, String>) -> Self {
+        let source = match (&panel.query, &panel.expr) {
+            (_, Some(expr)) => Some(QuerySource::Inline(expr.clone())),
+            (Some(name), None) => Some(QuerySource::Named(name.clone())),
+            (None, None) => None
+        };
+        QueryTarget {
+            server: panel.server.clone()
+                .or_else(|| dashboard.server.clone())
+                .unwrap_or_else(|| DEFAULT_SERVER_NAME.to_string()),
+            source,
+            vars: vars.clone(),
+            legend: panel.legend.clone(),
+            unit: panel.unit,
+            decimals: panel.decimals,
+        }
+    }
+
+    pub fn title(&self) -> String {
+        match &self.source {
+            Some(QuerySource::Named(name)) => format!("{} @ {}", name, self.server),
+            Some(QuerySource::Inline(expr)) => format!("{} @ {}", expr.trim(), self.server),
+            None => "no query".to_string()
+        }
+    }
+
+    pub fn render(&self, config: &ConfigFile) -> Result<String, ClientError> {
+        match &self.source {
+            Some(QuerySource::Named(name)) => render_query(config, name, &self.vars),
+            Some(QuerySource::Inline(expr)) => render_template(expr, &self.vars),
+            None => Err(ClientError::QueryNotFound("".to_string()))
+        }
+    }
+
+    // an explicit legend wins over the one stored with a named query
+    pub fn legend<'a>(&'a self, config: &'a ConfigFile) -> Option<&'a str> {
+        if let Some(l) = &self.legend {
+            return Some(l);
+        }
+        match &self.source {
+            Some(QuerySource::Named(name)) => config.queries.iter()
+                .find(|q| &q.name == name)
+                .and_then(|q| q.legend.as_deref()),
+            _ => None
+        }
+    }
+
+    // like the legend, the target's own unit and decimals win over the named query's
+    pub fn format(&self, config: &ConfigFile) -> ValueFormat {
+        let query = match &self.source {
+            Some(QuerySource::Named(name)) => config.queries.iter().find(|q| &q.name == name),
+            _ => None
+        };
+        ValueFormat::new(
+            self.unit.or_else(|| query.and_then(|q| q.unit)),
+            self.decimals.or_else(|| query.and_then(|q| q.decimals)),
+        )
+    }
+
+    pub fn validate(&self, config: &ConfigFile) -> Result<(), String> {
+        if !config.servers.iter().any(|s| s.name == self.server) {
+            return Err(ClientError::ServerNotFound(self.server.clone()).to_string());
+        }
+        match &self.source {
+            Some(QuerySource::Named(name)) if !config.queries.iter().any(|q| &q.name == name) => {
+                Err(ClientError::QueryNotFound(name.clone()).to_string())
+            }
+            None => Err("No query selected, pass a query name from the config or an inline expression with --expr".to_string()),
+            _ => Ok(())
+        }
+    }
+}
+
+pub fn legend_name(template: Option<&str>, labels: &Labels) -> String {
+    match template {
+        Some(t) => match render_template(t, labels) {
+            Ok(name) => name,
+            Err(e) => {
+                warn!("{}", e);
+                format_labels(labels)
+            }
+        },
+        None => format_labels(labels)
+    }
+}
+
+pub struct Series {
+    pub labels: Labels,
+    pub name: String,
+    pub points: Vec<(f64, f64)>,
+}
+
+impl Series {
+    // the newest sample, which is all the single-value panel kinds show
+    pub fn last(&self) -> Option<f64> {
+        self.points.last().map(|(_, v)| *v)
+    }
+}
+
+pub struct InFlight {
+    pub id: u64,
+    pub handle: JoinHandle<()>,
+}
+
+pub struct Panel {
+    pub title: String,
+    pub kind: PanelKind,
+    pub col_span: u16,
+    pub row_span: u16,
+    pub target: QueryTarget,
+    pub data: Vec<Series>,
+    pub window: (f64, f64),
+    pub error: Option<String>,
+    pub in_flight: Option<InFlight>,
+    // where the chart's plot lands on screen, for mapping mouse columns to time
+    pub plot_area: Rect,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub limit: usize,
+    pub thresholds: Vec<ThresholdRef>,
+    pub color_above: bool,
+    // table sort: 0 is the value column, then each label column in order
+    pub sort_column: usize,
+    pub sort_desc: bool,
+    // heatmap panels keep the buckets as well as the series
+    pub heatmap: Option<Heatmap>,
+    pub quantiles: Vec<f64>,
+    pub format: ValueFormat,
+}
+
+impl Panel {
+    pub fn new(title: String, target: QueryTarget, config: &ConfigFile) -> Self {
+        let error = match target.validate(config) {
+            Ok(()) => None,
+            Err(e) => {
+                warn!("{}: {}", title, e);
+                Some(e)
+            }
+        };
+        let format = target.format(config);
+        Panel {
+            title,
+            kind: PanelKind::default(),
+            col_span: 1,
+            row_span: 1,
+            target,
+            data: vec![],
+            window: (now_secs() - DEFAULT_TUI_RANGE_SECS, now_secs()),
+            error,
+            in_flight: None,
+            plot_area: Rect::default(),
+            min: None,
+            max: None,
+            limit: DEFAULT_BAR_LIMIT,
+            thresholds: vec![],
+            color_above: false,
+            sort_column: 0,
+            sort_desc: true,
+            heatmap: None,
+            quantiles: vec![],
+            format,
+        }
+    }
+
+    pub fn from_ref(dashboard: &DashboardRef, panel: &PanelRef, vars: &BTreeMap<String, String>, config: &ConfigFile) -> Self {
+        let target = QueryTarget::from_panel(dashboard, panel, vars);
+        let title = panel.title.clone().unwrap_or_else(|| target.title());
+        Panel {
+            kind: panel.kind,
+            col_span: panel.col_span.max(1),
+            row_span: panel.row_span.max(1),
+            min: panel.min,
+            max: panel.max,
+            limit: panel.limit.unwrap_or(DEFAULT_BAR_LIMIT),
+            thresholds: panel.thresholds.clone(),
+            color_above: panel.color_above,
+            quantiles: panel.quantiles.clone(),
+            ..Panel::new(title, target, config)
+        }
+    }
+
+    // a panel whose only content is an error, e.g. for a dashboard that doesn't exist
+    pub fn error(title: String, error: String) -> Self {
+        Panel {
+            title,
+            kind: PanelKind::default(),
+            col_span: 1,
+            row_span: 1,
+            target: QueryTarget { server: DEFAULT_SERVER_NAME.to_string(), source: None, vars: BTreeMap::new(), legend: None, unit: None, decimals: None },
+            data: vec![],
+            window: (now_secs() - DEFAULT_TUI_RANGE_SECS, now_secs()),
+            error: Some(error),
+            in_flight: None,
+            plot_area: Rect::default(),
+            min: None,
+            max: None,
+            limit: DEFAULT_BAR_LIMIT,
+            thresholds: vec![],
+            color_above: false,
+            sort_column: 0,
+            sort_desc: true,
+            heatmap: None,
+            quantiles: vec![],
+            format: ValueFormat::default(),
+        }
+    }
+
+    pub fn to_series(&self, data: QueryData, config: &ConfigFile) -> Vec<Series> {
+        let legend = self.target.legend(config);
+        match data {
+            QueryData::Matrix(series) => {
+                debug!("rsdata contains {} results", series.len());
+                series.into_iter()
+                    .map(|s| Series {
+                        name: legend_name(legend, &s.metric),
+                        points: s.values.iter().map(|v| (v.timestamp(), v.value())).collect(),
+                        labels: s.metric,
+                    })
+                    .collect()
+            }
+            // instant queries, for the panel kinds that only show the current value
+            QueryData::Vector(samples) => {
+                samples.into_iter()
+                    .filter_map(|s| {
+                        let value = s.value.as_ref()?;
+                        Some(Series {
+                            name: legend_name(legend, &s.metric),
+                            points: vec![(value.timestamp(), value.value())],
+                            labels: s.metric,
+                        })
+                    })
+                    .collect()
+            }
+            QueryData::Scalar(value) => vec![Series {
+                labels: Labels::new(),
+                name: self.target.title(),
+                points: vec![(value.timestamp(), value.value())],
+            }],
+            other => {
+                warn!("Expected a matrix or vector result but got {}", other.result_type());
+                vec![]
+            }
+        }
+    }
+
+    // the label names across every series, which become a table panel's columns
+    pub fn label_keys(&self) -> Vec<&str> {
+        let mut keys: Vec<&str> = self.data.iter()
+            .flat_map(|s| s.labels.keys().map(|k| k.as_str()))
+            .collect();
+        keys.sort();
+        keys.dedup();
+        keys
+    }
+
+    // series in table order: by value, or by the label column picked with `s`
+    pub fn sorted_series(&self) -> Vec<&Series> {
+        let keys = self.label_keys();
+        let mut rows: Vec<&Series> = self.data.iter().collect();
+        match self.sort_column.checked_sub(1).and_then(|i| keys.get(i)) {
+            Some(key) => rows.sort_by(|a, b| a.labels.get(*key).cmp(&b.labels.get(*key))),
+            // series without a number sort below every value
+            None => {
+                let value = |s: &Series| s.last().filter(|v| !v.is_nan()).unwrap_or(f64::NEG_INFINITY);
+                rows.sort_by(|a, b| value(a).total_cmp(&value(b)))
+            }
+        }
+        if self.sort_desc {
+            rows.reverse();
+        }
+        rows
+    }
+
+    // steps the sort through the value column and then each label column
+    pub fn cycle_sort(&mut self) {
+        self.sort_column = (self.sort_column + 1) % (self.label_keys().len() + 1);
+    }
+
+    // the color of the highest threshold the value reaches, if any
+    pub fn threshold_color(&self, value: f64) -> Option<Color> {
+        self.thresholds.iter()
+            .filter(|t| value >= t.value)
+            .max_by(|a, b| a.value.total_cmp(&b.value))
+            .and_then(|t| t.color())
+    }
+
+    pub fn sample_times(&self) -> Vec<f64> {
+        let mut times: Vec<f64> = self.data.iter()
+            .flat_map(|s| s.points.iter().map(|(t, _)| *t))
+            .collect();
+        times.sort_by(|a, b| a.total_cmp(b));
+        times.dedup();
+        times
+    }
+
+    pub fn contains(&self, column: u16, row: u16) -> bool {
+        let area = self.plot_area;
+        area.width > 0 && column >= area.x && column < area.x + area.width && row >= area.y && row < area.y + area.height
+    }
+
+    pub fn time_at_column(&self, column: u16) -> f64 {
+        let (start, end) = self.window;
+        start + (column.saturating_sub(self.plot_area.x)) as f64 / self.plot_area.width.max(1) as f64 * (end - start)
+    }
+
+    // each series' name, palette index and the value of its sample nearest to the cursor
+    pub fn cursor_values(&self, c: f64) -> Vec<(usize, &str, Option<f64>)> {
+        self.data.iter()
+            .enumerate()
+            .map(|(i, s)| {
+                let value = s.points.iter()
+                    .min_by(|a, b| (a.0 - c).abs().total_cmp(&(b.0 - c).abs()))
+                    .map(|(_, v)| *v);
+                (i, s.name.as_str(), value)
+            })
+            .collect()
+    }
+}
@@ -1,21 +1,216 @@
-use serde::Deserialize;
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
 //{"status":"success","data":{"resultType":"vector","result":[]}}
+//{"status":"error","errorType":"bad_data","error":"parse error..."}
 
-#[derive(Deserialize, Debug)]
-pub struct Points {
-    pub metric: serde_json::Value,
-    pub values: Vec<serde_json::Value>
+pub type Labels = BTreeMap<String, String>;
+
+// [ <unix_time>, "<sample_value>" ]
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SamplePair(pub f64, pub String);
+
+impl SamplePair {
+    pub fn timestamp(&self) -> f64 {
+        self.0
+    }
+
+    // prometheus encodes values as strings so that NaN and +Inf/-Inf survive JSON
+    pub fn value(&self) -> f64 {
+        parse_sample_value(&self.1)
+    }
+}
+
+// [ <unix_time>, { "count": .., "sum": .., "buckets": [..] } ]
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct HistogramPair(pub f64, pub NativeHistogram);
+
+impl HistogramPair {
+    pub fn timestamp(&self) -> f64 {
+        self.0
+    }
 }
 
-#[derive(Deserialize, Debug)]
-pub struct Datum {
-    #[serde(rename="resultType")]
-    pub result_type: String,
-    pub result: Vec<Points>
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct NativeHistogram {
+    pub count: String,
+    pub sum: String,
+    #[serde(default)]
+    pub buckets: Vec<HistogramBucket>,
+}
+
+// [ <boundary_rule>, "<left_boundary>", "<right_boundary>", "<count_in_bucket>" ]
+// boundary_rule: 0 open left, 1 open right, 2 open both, 3 closed both
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct HistogramBucket(pub u8, pub String, pub String, pub String);
+
+impl HistogramBucket {
+    pub fn lower(&self) -> f64 {
+        parse_sample_value(&self.1)
+    }
+
+    pub fn upper(&self) -> f64 {
+        parse_sample_value(&self.2)
+    }
+
+    pub fn count(&self) -> f64 {
+        parse_sample_value(&self.3)
+    }
 }
-#[derive(Deserialize, Debug)]
-pub struct PromResponse {
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct InstantSample {
+    #[serde(default)]
+    pub metric: Labels,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<SamplePair>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub histogram: Option<HistogramPair>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RangeSeries {
+    #[serde(default)]
+    pub metric: Labels,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub values: Vec<SamplePair>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub histograms: Vec<HistogramPair>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "resultType", content = "result", rename_all = "lowercase")]
+pub enum QueryData {
+    Vector(Vec<InstantSample>),
+    Matrix(Vec<RangeSeries>),
+    Scalar(SamplePair),
+    String(SamplePair),
+}
+
+impl QueryData {
+    pub fn result_type(&self) -> &'static str {
+        match self {
+            QueryData::Vector(_) => "vector",
+            QueryData::Matrix(_) => "matrix",
+            QueryData::Scalar(_) => "scalar",
+            QueryData::String(_) => "string",
+        }
+    }
+
+    // number of series (or 1 for scalar/string results)
+    pub fn len(&self) -> usize {
+        match self {
+            QueryData::Vector(v) => v.len(),
+            QueryData::Matrix(m) => m.len(),
+            QueryData::Scalar(_) | QueryData::String(_) => 1,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PromResponse<T = QueryData> {
     pub status: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub data: Option<Datum>
-}
\ No newline at end of file
+    #[serde(default = "Option::default", skip_serializing_if = "Option::is_none")]
+    pub data: Option<T>,
+    #[serde(rename = "errorType", default, skip_serializing_if = "Option::is_none")]
+    pub error_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub infos: Vec<String>,
+}
+
+// one entry of /api/v1/metadata; a metric may have several when targets disagree
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct MetricMetadata {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub help: String,
+    #[serde(default)]
+    pub unit: String,
+}
+
+pub type Metadata = BTreeMap<String, Vec<MetricMetadata>>;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleType {
+    Alerting,
+    Recording,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertState {
+    Firing,
+    Pending,
+    Inactive,
+}
+
+// {"groups":[{"name":"api","file":"rules.yml","rules":[{"type":"alerting","name":"HighLatency",...}]}]}
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RulesData {
+    #[serde(default)]
+    pub groups: Vec<RuleGroup>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RuleGroup {
+    pub name: String,
+    #[serde(default)]
+    pub file: String,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Rule {
+    #[serde(rename = "type")]
+    pub kind: RuleType,
+    pub name: String,
+    pub query: String,
+    // an alerting rule's `for`, in seconds: how long an alert stays pending before it fires
+    #[serde(default)]
+    pub duration: f64,
+    #[serde(default)]
+    pub labels: Labels,
+    #[serde(default)]
+    pub annotations: Labels,
+    #[serde(default)]
+    pub alerts: Vec<Alert>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Alert {
+    #[serde(default)]
+    pub labels: Labels,
+    #[serde(default)]
+    pub annotations: Labels,
+    pub state: AlertState,
+    // RFC3339, when the alert became pending
+    #[serde(rename = "activeAt", default, skip_serializing_if = "Option::is_none")]
+    pub active_at: Option<String>,
+    #[serde(default)]
+    pub value: String,
+}
+
+pub fn parse_sample_value(s: &str) -> f64 {
+    match s {
+        "+Inf" | "Inf" => f64::INFINITY,
+        "-Inf" => f64::NEG_INFINITY,
+        _ => s.parse::<f64>().unwrap_or(f64::NAN)
+    }
+}
+
+// {__name__="up", job="node"} style rendering, as prometheus itself shows series
+pub fn format_labels(labels: &Labels) -> String {
+    let name = labels.get("__name__").map(String::as_str).unwrap_or("");
+    let pairs = itertools::join(
+        labels.iter()
+            .filter(|(k, _)| k.as_str() != "__name__")
+            .map(|(k, v)| format!("{}=\"{}\"", k, v)),
+        ", ");
+    format!("{}{{{}}}", name, pairs)
+}
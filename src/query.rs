@@ -1,104 +1,76 @@
-use std::collections::{BTreeMap, HashMap};
-use crate::cfg_file::{ConfigFile, QueryRef, ServerRef};
-use crate::cli::Query;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::BTreeMap;
+use crate::cfg_file::ConfigFile;
+use crate::cli::{Query, Range};
+use crate::client::{render_query, ApiResult, PromClient};
+use crate::timespec::{auto_step, now_secs, parse_duration, parse_time};
 use anyhow::bail;
-use reqwest::blocking::Client;
-use crate::prometheus::{PromResponse};
-use handlebars::Handlebars;
-
-pub fn execute_query(config: &ConfigFile, args: &Query) -> anyhow::Result<()> {
-    let servername = match &args.server {
-        Some(s) => s.to_string(),
-        _ => "default".to_string()
-    };
-    let server: Option<&ServerRef> = config.servers.iter().find(|s| s.name == servername);
-    if server.is_none() {
-        anyhow::bail!("Can't find a default server to query, please specify default server in config.toml or specify server via -s");
-    }
-
-    let query: Option<&QueryRef> = config.queries.iter().find(|q| Some(&q.name) == args.query.as_ref());
-
-    let query_string = match query {
-        Some(e) => &e.query,
-        None => {
-            bail!("There was no query by that name.")
-        }
-    };
-    let query_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-    let full_url: String = format!("{}/api/v1/query", server.unwrap().url);
-
-    let handlebars = Handlebars::new();
+use crate::prometheus::QueryData;
+use crate::output::{write_output, OutputFormat};
+use crate::units::{Unit, ValueFormat};
 
+pub fn parse_eval_args(eval: &[String]) -> anyhow::Result<BTreeMap<String, String>> {
     let mut data = BTreeMap::new();
-    for e in args.eval.clone() {
-        let val: Vec<String> = e.split("=").map(str::to_string).collect();
-        data.insert(val[0].clone(), val[1].clone());
+    for e in eval {
+        match e.split_once('=') {
+            Some((key, val)) => { data.insert(key.to_string(), val.to_string()); }
+            None => bail!("Template argument '{}' is not in key=value form", e)
+        }
     }
-    let interp_string = handlebars.render_template(&query_string, &data)?;
-    let rs = reqwest::blocking::Client::new()
-        .post(&full_url)
-        .form(&[
-            ("query", interp_string),
-            ("time", query_time.to_string())
-        ])
-        .send()?;
-    let result = match rs.text() {
-        Ok(s) => s,
-        Err(e) => bail!("no text from response: {}", e)
-    };
-    let rsjson: PromResponse = serde_json::from_str::<PromResponse>(&result)?;
-    rsjson.data.unwrap().result.iter().for_each(|datum| {
-        //info!("{}: {}", datum.value[0], datum.value[1]);
-    });
-    Ok(())
+    Ok(data)
 }
 
-pub fn execute_query_range(config: &ConfigFile, args: &Query) -> anyhow::Result<()> {
-    let servername = match &args.server {
-        Some(s) => s.to_string(),
-        _ => "default".to_string()
-    };
-    let server: Option<&ServerRef> = config.servers.iter().find(|s| s.name == servername);
-    if server.is_none() {
-        anyhow::bail!("Can't find a default server to query, please specify default server in config.toml or specify server via -s");
+fn query_name(query: &Option<String>) -> anyhow::Result<&str> {
+    match query {
+        Some(q) => Ok(q.as_str()),
+        None => bail!("Please specify the name of a query from the config")
     }
+}
 
-    let query: Option<&QueryRef> = config.queries.iter().find(|q| Some(&q.name) == args.query.as_ref());
+// --unit and --decimals win over the named query's settings
+fn value_format(config: &ConfigFile, name: &str, unit: Option<Unit>, decimals: Option<usize>) -> ValueFormat {
+    let query = config.queries.iter().find(|q| q.name == name);
+    ValueFormat::new(
+        unit.or_else(|| query.and_then(|q| q.unit)),
+        decimals.or_else(|| query.and_then(|q| q.decimals)),
+    )
+}
 
-    let query_string = match query {
-        Some(e) => &e.query,
-        None => {
-            bail!("There was no query by that name.")
-        }
-    };
-    let query_start = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()-3600;
-    let query_end = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-    let full_url: String = format!("{}/api/v1/query_range", server.unwrap().url);
+fn print_result(format: OutputFormat, units: &ValueFormat, result: &ApiResult<QueryData>) -> anyhow::Result<usize> {
+    for w in &result.warnings {
+        eprintln!("Warning: {}", w);
+    }
+    write_output(&mut std::io::stdout().lock(), format, units, &result.raw, &result.data)?;
+    Ok(result.data.len())
+}
 
-    let handlebars = Handlebars::new();
+pub async fn execute_query(config: &ConfigFile, args: &Query) -> anyhow::Result<usize> {
+    let client = PromClient::from_config(config, args.server.as_deref())?;
+    let data = parse_eval_args(&args.eval)?;
+    let name = query_name(&args.query)?;
+    let interp_string = render_query(config, name, &data)?;
+    let result = client.query(&interp_string, now_secs()).await?;
+    print_result(args.output, &value_format(config, name, args.unit, args.decimals), &result)
+}
 
-    let mut data = BTreeMap::new();
-    for e in args.eval.clone() {
-        let val: Vec<String> = e.split("=").map(str::to_string).collect();
-        data.insert(val[0].clone(), val[1].clone());
+pub async fn execute_query_range(config: &ConfigFile, args: &Range) -> anyhow::Result<usize> {
+    let client = PromClient::from_config(config, args.server.as_deref())?;
+    let data = parse_eval_args(&args.eval)?;
+    let name = query_name(&args.query)?;
+    let interp_string = render_query(config, name, &data)?;
+
+    let now = now_secs();
+    let query_start = parse_time(&args.start, now)?;
+    let query_end = parse_time(&args.end, now)?;
+    if query_start >= query_end {
+        bail!("Range start ({}) must be before range end ({})", args.start, args.end);
     }
-    let interp_string = handlebars.render_template(&query_string, &data)?;
-    let rs = reqwest::blocking::Client::new()
-        .post(&full_url)
-        .form(&[
-            ("query", interp_string),
-            ("start", query_start.to_string()),
-            ("end", query_end.to_string())
-        ])
-        .send()?;
-    let result = match rs.text() {
-        Ok(s) => s,
-        Err(e) => bail!("no text from response: {}", e)
+    let query_step = match &args.step {
+        Some(s) => parse_duration(s)?.as_secs_f64(),
+        None => auto_step(query_start, query_end)
     };
-    let rsjson: PromResponse = serde_json::from_str::<PromResponse>(&result)?;
-    rsjson.data.unwrap().result.iter().for_each(|datum| {
-       // info!("{}: {}", datum.value[0], datum.value[1]);
-    });
-    Ok(())
-}
\ No newline at end of file
+    if query_step <= 0.0 {
+        bail!("Step must be greater than zero");
+    }
+    let result = client.query_range(&interp_string, query_start, query_end, query_step).await?;
+    print_result(args.output, &value_format(config, name, args.unit, args.decimals), &result)
+}
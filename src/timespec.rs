@@ -0,0 +1,126 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use anyhow::{anyhow, bail};
+use crate::consts::*;
+
+pub fn now_secs() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs_f64()
+}
+
+// Accepts "now", "now-6h", "now+15m", "-30m", a unix epoch or an RFC3339 timestamp.
+pub fn parse_time(spec: &str, now: f64) -> anyhow::Result<f64> {
+    let spec = spec.trim();
+    if spec.is_empty() {
+        bail!("empty time expression");
+    }
+    let relative = match spec.strip_prefix("now") {
+        Some("") => return Ok(now),
+        Some(rest) => Some(rest),
+        None if spec.starts_with('-') => Some(spec),
+        None => None
+    };
+    if let Some(rest) = relative {
+        let (sign, dur) = match (rest.strip_prefix('-'), rest.strip_prefix('+')) {
+            (Some(d), _) => (-1.0, d),
+            (_, Some(d)) => (1.0, d),
+            _ => bail!("'{}' is not a valid relative time, expected something like now-6h", spec)
+        };
+        let offset = parse_duration(dur)?;
+        return Ok(now + sign * offset.as_secs_f64());
+    }
+    if let Ok(epoch) = spec.parse::<f64>() {
+        return Ok(epoch);
+    }
+    match humantime::parse_rfc3339_weak(spec) {
+        Ok(t) => Ok(t.duration_since(UNIX_EPOCH)?.as_secs_f64()),
+        Err(e) => Err(anyhow!("'{}' is not an RFC3339 timestamp, unix epoch or relative time: {}", spec, e))
+    }
+}
+
+// RFC3339 with any UTC offset, as prometheus reports alert times; humantime only takes Z
+pub fn parse_rfc3339(stamp: &str) -> Option<f64> {
+    let split = stamp.len().checked_sub(6).filter(|i| stamp.is_char_boundary(*i));
+    let (base, offset) = match split.map(|i| stamp.split_at(i)) {
+        Some((base, off)) if (off.starts_with('+') || off.starts_with('-')) && off.as_bytes()[3] == b':' => {
+            let secs = off[1..3].parse::<f64>().ok()? * 3600.0 + off[4..6].parse::<f64>().ok()? * 60.0;
+            (base, if off.starts_with('-') { -secs } else { secs })
+        }
+        _ => (stamp, 0.0)
+    };
+    let t = humantime::parse_rfc3339_weak(base).ok()?;
+    Some(t.duration_since(UNIX_EPOCH).ok()?.as_secs_f64() - offset)
+}
+
+// Accepts humantime durations ("1m", "1h 30m") as well as bare seconds ("15", "0.5").
+pub fn parse_duration(spec: &str) -> anyhow::Result<Duration> {
+    let spec = spec.trim();
+    if let Ok(secs) = spec.parse::<f64>() {
+        // negative, NaN, infinite or too large for a Duration
+        return Duration::try_from_secs_f64(secs).map_err(|_| anyhow!("'{}' is not a valid duration", spec));
+    }
+    humantime::parse_duration(spec).map_err(|e| anyhow!("'{}' is not a valid duration: {}", spec, e))
+}
+
+// Picks a whole-second step that yields roughly DEFAULT_RANGE_POINTS samples across the window.
+pub fn auto_step(start: f64, end: f64) -> f64 {
+    ((end - start) / DEFAULT_RANGE_POINTS as f64).ceil().max(1.0)
+}
+
+// Short tick label for a chart axis: time of day for windows under a day, otherwise date and time.
+pub fn format_tick(ts: f64, span_secs: f64) -> String {
+    let t = UNIX_EPOCH + Duration::from_secs(ts.max(0.0) as u64);
+    let stamp = humantime::format_rfc3339_seconds(t).to_string();
+    // 2024-01-31T12:34:56Z
+    if span_secs < 86400.0 {
+        stamp[11..19].to_string()
+    } else {
+        format!("{} {}", &stamp[5..10], &stamp[11..16])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: f64 = 1_700_000_000.0;
+
+    #[test]
+    fn relative_times() {
+        assert_eq!(parse_time("now", NOW).unwrap(), NOW);
+        assert_eq!(parse_time(" now ", NOW).unwrap(), NOW);
+        assert_eq!(parse_time("now-6h", NOW).unwrap(), NOW - 6.0 * 3600.0);
+        assert_eq!(parse_time("now+15m", NOW).unwrap(), NOW + 900.0);
+        assert_eq!(parse_time("-30m", NOW).unwrap(), NOW - 1800.0);
+        assert_eq!(parse_time("now-90", NOW).unwrap(), NOW - 90.0);
+    }
+
+    #[test]
+    fn absolute_times() {
+        assert_eq!(parse_time("1700000123.5", NOW).unwrap(), 1_700_000_123.5);
+        assert_eq!(parse_time("2023-11-14T22:13:20Z", NOW).unwrap(), NOW);
+    }
+
+    #[test]
+    fn invalid_times_are_errors() {
+        for spec in ["", "now6h", "now-", "now-soon", "yesterday", "nowé", "-é", "now+é", "now-1e20"] {
+            assert!(parse_time(spec, NOW).is_err(), "{:?} should not parse", spec);
+        }
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("15").unwrap(), Duration::from_secs(15));
+        assert_eq!(parse_duration("0.5").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("1h 30m").unwrap(), Duration::from_secs(5400));
+        assert!(parse_duration("-1").is_err());
+        assert!(parse_duration("inf").is_err());
+        assert!(parse_duration("NaN").is_err());
+        assert!(parse_duration("1e20").is_err());
+        assert!(parse_duration("soon").is_err());
+    }
+
+    #[test]
+    fn auto_step_is_whole_seconds() {
+        assert_eq!(auto_step(0.0, 10.0), 1.0);
+        assert_eq!(auto_step(0.0, 3600.0), (3600.0 / DEFAULT_RANGE_POINTS as f64).ceil());
+    }
+}
@@ -1,4 +1,4 @@
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime};
 use ratatui::{
     backend::{Backend, CrosstermBackend},
     layout::{Constraint, Direction, Layout},
@@ -9,11 +9,52 @@ use ratatui::{
     Frame, Terminal,
 };
 use ratatui::layout::Alignment;
-use ratatui::widgets::{Paragraph, Wrap};
+use ratatui::layout::Rect;
+use ratatui::text::Spans;
+use ratatui::widgets::{BarChart, Clear, Gauge, List, ListItem, ListState, Paragraph, Row, Sparkline, Table, Wrap};
 use crate::consts::*;
 use crate::AppData;
+use crate::alerts::ActiveAlert;
+use crate::cfg_file::{PanelKind, ThresholdRef};
+use crate::editor::EditorMode;
+use crate::heatmap::Heatmap;
+use crate::panel::{Panel, Series};
+use crate::prometheus::{format_labels, AlertState, Labels};
+use crate::timespec::{format_tick, now_secs};
+use crate::units::ValueFormat;
 // ui
 
+const SERIES_PALETTE: [Color; 10] = [
+    Color::Cyan,
+    Color::Yellow,
+    Color::Green,
+    Color::Magenta,
+    Color::LightRed,
+    Color::LightBlue,
+    Color::LightGreen,
+    Color::LightYellow,
+    Color::LightMagenta,
+    Color::White,
+];
+
+// heatmap cells run from dark blue for the quietest buckets to red for the busiest
+const HEATMAP_GRADIENT: [(u8, u8, u8); 5] = [
+    (20, 30, 90),
+    (30, 110, 180),
+    (60, 180, 120),
+    (230, 200, 50),
+    (220, 50, 40),
+];
+
+const ALERT_MARKER_COLOR: Color = Color::LightRed;
+
+const QUANTILE_PALETTE: [Color; 4] = [
+    Color::White,
+    Color::LightMagenta,
+    Color::LightRed,
+    Color::Black,
+];
+
 pub fn ui<B: Backend>(f: &mut Frame<B>, app: &mut AppData) {
 
     //region Pane Setup
@@ -30,54 +71,18 @@ pub fn ui<B: Backend>(f: &mut Frame<B>, app: &mut AppData) {
         ).split(size);
     //endregion
 
-    //region Line Chart
-    let time_start:f64 = {
-        let s = SystemTime::now();
-        (s.duration_since(UNIX_EPOCH).unwrap() - Duration::from_secs(3600)).as_secs_f64()
-    };
-    let time_end:f64 = {
-        let s = SystemTime::now();
-        s.duration_since(UNIX_EPOCH).unwrap().as_secs_f64()
-    };
-    let datasets = vec![
-        Dataset::default()
-            .name("results")
-            .graph_type(GraphType::Line)
-            .marker(symbols::Marker::Dot)
-            .style(Style::default()
-                .bg(Color::LightBlue)
-                .fg(Color::Cyan))
-            .data(&app.data)];
-    let chart = Chart::new(datasets)
-        .block(
-            Block::default()
-                .title(Span::styled(
-                    "Chart 1",
-                    Style::default()
-                        .fg(Color::Cyan)
-                        .add_modifier(Modifier::BOLD),
-                ))
-                .borders(Borders::ALL),
-        )
-        .x_axis(
-            Axis::default()
-                .title("X Axis")
-                .style(Style::default().fg(Color::Gray))
-                .bounds([0.0,1.0]),
-        )
-        .y_axis(
-            Axis::default()
-                .title("Y Axis")
-                .style(Style::default().fg(Color::Gray))
-                .labels(vec![
-                    Span::styled("0.0", Style::default().add_modifier(Modifier::BOLD)),
-                    Span::raw("0"),
-                    Span::styled("1.0", Style::default().add_modifier(Modifier::BOLD)),
-                ])
-                .bounds([time_start, time_end]),
-        );
-    debug!("Chart: {:#?}", chart);
-    f.render_widget(chart, panes[1]);
+    //region Panel Grid
+    let areas = grid(panes[1], app.columns, &app.panels);
+    let cursor = app.cursor;
+    let focused = app.focused;
+    let highlight = app.panels.len() > 1;
+    let markers: Vec<Vec<f64>> = app.panels.iter().map(|p| app.alert_markers(&p.target.server)).collect();
+    for (i, (panel, area)) in app.panels.iter_mut().zip(areas).enumerate() {
+        draw_panel(f, panel, area, cursor, &markers[i], i == focused, highlight);
+    }
+    render_alerts(f, app, panes[1]);
+    render_picker(f, app, panes[1]);
+    render_editor(f, app, panes[1]);
     //endregion
 
     //region Log Pane
@@ -97,7 +102,19 @@ pub fn ui<B: Backend>(f: &mut Frame<B>, app: &mut AppData) {
     //endregion
 
     //region Bottom Status Line
-    let bottom_line = Paragraph::new(format!("{}",humantime::format_rfc3339_seconds(SystemTime::now())));
+    let fetching = match app.in_flight_secs() {
+        Some(secs) => format!("  |  ⟳ fetching {:.1}s", secs),
+        None => "".to_string()
+    };
+    let variables = match app.variables.is_empty() {
+        true => "".to_string(),
+        false => format!("  |  {}", itertools::join(app.variables.iter().map(|v| v.label()), "  "))
+    };
+    let bottom_line = Paragraph::new(format!("{}  |  {}{}{}  |  v variables  a alerts  +/- zoom  [/] pan  n now  r reload  / query  s/S sort  ←/→ cursor  tab focus  m menu",
+                                             humantime::format_rfc3339_seconds(SystemTime::now()),
+                                             app.range_label(),
+                                             fetching,
+                                             variables));
     f.render_widget(bottom_line, panes[3]);
     //endregion
 
@@ -108,6 +125,901 @@ pub fn ui<B: Backend>(f: &mut Frame<B>, app: &mut AppData) {
     //endregion
 }
 
+// Flows panels left to right into rows of `columns` cells; a row is as tall as its tallest row_span.
+fn grid(area: Rect, columns: u16, panels: &[Panel]) -> Vec<Rect> {
+    let columns = columns.max(1);
+    let mut rows: Vec<Vec<usize>> = vec![];
+    let mut used = columns;
+    for (i, p) in panels.iter().enumerate() {
+        let span = p.col_span.min(columns);
+        if used + span > columns {
+            rows.push(vec![]);
+            used = 0;
+        }
+        rows.last_mut().unwrap().push(i);
+        used += span;
+    }
+    let heights: Vec<u32> = rows.iter()
+        .map(|r| r.iter().map(|i| panels[*i].row_span as u32).max().unwrap_or(1))
+        .collect();
+    let total: u32 = heights.iter().sum();
+    let row_areas = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(heights.iter().map(|h| Constraint::Ratio(*h, total.max(1))).collect::<Vec<_>>())
+        .split(area);
+
+    let mut retval = vec![Rect::default(); panels.len()];
+    for (row, row_area) in rows.iter().zip(row_areas.iter()) {
+        let cells = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints(row.iter()
+                .map(|i| Constraint::Ratio(panels[*i].col_span.min(columns) as u32, columns as u32))
+                .collect::<Vec<_>>())
+            .split(*row_area);
+        for (i, cell) in row.iter().zip(cells.iter()) {
+            retval[*i] = *cell;
+        }
+    }
+    retval
+}
+
+// the cursor line is drawn on every panel, but only the focused one gets the value popup
+fn draw_panel<B: Backend>(f: &mut Frame<B>, panel: &mut Panel, area: Rect, cursor: Option<f64>, markers: &[f64], focused: bool, highlight: bool) {
+    let title_style = match focused && highlight {
+        true => Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD),
+        false => Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD)
+    };
+    if let Some(err) = &panel.error {
+        let error = Paragraph::new(err.as_str())
+            .style(Style::default().fg(Color::Red))
+            .block(create_dialog_block(&panel.title))
+            .wrap(Wrap { trim: true });
+        f.render_widget(error, area);
+        return;
+    }
+    if panel.kind != PanelKind::Graph {
+        // only charts map mouse columns to time
+        panel.plot_area = Rect::default();
+    }
+    match panel.kind {
+        PanelKind::Graph => draw_graph(f, panel, area, cursor, markers, focused, title_style),
+        PanelKind::Stat => draw_stat(f, panel, area, title_style),
+        PanelKind::Gauge => draw_gauge(f, panel, area, title_style),
+        PanelKind::Bar => draw_bar(f, panel, area, title_style),
+        PanelKind::Sparkline => draw_sparkline(f, panel, area, title_style),
+        PanelKind::Table => draw_table(f, panel, area, title_style),
+        PanelKind::Heatmap => draw_heatmap(f, panel, area, title_style),
+    }
+}
+
+fn panel_block(title: &str, title_style: Style) -> Block<'_> {
+    Block::default()
+        .title(Span::styled(title, title_style))
+        .borders(Borders::ALL)
+}
+
+//region Single-Value Panels
+// 3-row glyphs for the digits a stat shows; anything else is drawn as plain text on the middle row
+fn big_glyph(c: char) -> [&'static str; 3] {
+    match c {
+        '0' => ["┌─┐", "│ │", "└─┘"],
+        '1' => [" ┐ ", " │ ", " ┴ "],
+        '2' => ["╶─┐", "┌─┘", "└─╴"],
+        '3' => ["╶─┐", " ─┤", "╶─┘"],
+        '4' => ["╷ ╷", "└─┤", "  ╵"],
+        '5' => ["┌─╴", "└─┐", "╶─┘"],
+        '6' => ["┌─╴", "├─┐", "└─┘"],
+        '7' => ["╶─┐", "  │", "  ╵"],
+        '8' => ["┌─┐", "├─┤", "└─┘"],
+        '9' => ["┌─┐", "└─┤", "╶─┘"],
+        '.' => [" ", " ", "▪"],
+        '-' => ["   ", "╶─╴", "   "],
+        _ => ["", "", ""]
+    }
+}
+
+fn big_text(text: &str) -> [String; 3] {
+    let mut rows = [String::new(), String::new(), String::new()];
+    for c in text.chars() {
+        let glyph = big_glyph(c);
+        match glyph[1].is_empty() {
+            true => {
+                rows[0].push(' ');
+                rows[1].push(c);
+                rows[2].push(' ');
+            }
+            false => {
+                for (row, part) in rows.iter_mut().zip(glyph) {
+                    row.push_str(part);
+                }
+            }
+        }
+    }
+    rows
+}
+
+// one big number per series, side by side, colored by the highest threshold it reaches
+fn draw_stat<B: Backend>(f: &mut Frame<B>, panel: &Panel, area: Rect, title_style: Style) {
+    let block = panel_block(&panel.title, title_style);
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+    if panel.data.is_empty() {
+        return;
+    }
+    let cells = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(vec![Constraint::Ratio(1, panel.data.len() as u32); panel.data.len()])
+        .split(inner);
+    for (series, cell) in panel.data.iter().zip(cells.iter()) {
+        let value = series.last();
+        let color = value.and_then(|v| panel.threshold_color(v)).unwrap_or(Color::Green);
+        let style = Style::default().fg(color).add_modifier(Modifier::BOLD);
+        let text = panel.format.format_opt(value);
+        let big = big_text(&text);
+        let mut lines: Vec<Spans> = match big[0].chars().count() as u16 <= cell.width && cell.height >= 4 {
+            true => big.iter().map(|r| Spans::from(Span::styled(r.clone(), style))).collect(),
+            false => vec![Spans::from(Span::styled(text, style))]
+        };
+        lines.push(Spans::from(Span::styled(series.name.as_str(), Style::default().fg(Color::Gray))));
+        let top = cell.height.saturating_sub(lines.len() as u16) / 2;
+        let rect = Rect::new(cell.x, cell.y + top, cell.width, cell.height - top);
+        f.render_widget(Paragraph::new(lines).alignment(Alignment::Center), rect);
+    }
+}
+
+// a horizontal gauge per series, scaled between the panel's min and max
+fn draw_gauge<B: Backend>(f: &mut Frame<B>, panel: &Panel, area: Rect, title_style: Style) {
+    let block = panel_block(&panel.title, title_style);
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+    let rows = inner.height.max(1) as usize;
+    let shown = panel.data.len().min(rows);
+    if shown == 0 {
+        return;
+    }
+    let min = panel.min.unwrap_or(0.0);
+    let max = panel.max.unwrap_or(DEFAULT_GAUGE_MAX);
+    let cells = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(vec![Constraint::Ratio(1, shown as u32); shown])
+        .split(inner);
+    for (series, cell) in panel.data.iter().zip(cells.iter()) {
+        let value = series.last();
+        let color = value.and_then(|v| panel.threshold_color(v)).unwrap_or(Color::Green);
+        let ratio = match value {
+            Some(v) if v.is_finite() && max > min => ((v - min) / (max - min)).clamp(0.0, 1.0),
+            _ => 0.0
+        };
+        // leave a blank row between gauges when there's room
+        let cell = match cell.height > 2 {
+            true => Rect::new(cell.x, cell.y, cell.width, cell.height - 1),
+            false => *cell
+        };
+        let gauge = Gauge::default()
+            .gauge_style(Style::default().fg(color).bg(Color::Black))
+            .ratio(ratio)
+            .label(Span::styled(format!("{}  {}", series.name, panel.format.format_opt(value)), Style::default().fg(Color::White).add_modifier(Modifier::BOLD)));
+        f.render_widget(gauge, cell);
+    }
+}
+//endregion
+
+//region Bar, Sparkline and Table Panels
+// the largest `limit` series as bars, numbered, with a legend giving each number's series and value
+fn draw_bar<B: Backend>(f: &mut Frame<B>, panel: &Panel, area: Rect, title_style: Style) {
+    let block = panel_block(&panel.title, title_style);
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+    let mut series: Vec<(&Series, f64)> = panel.data.iter()
+        .filter_map(|s| s.last().filter(|v| v.is_finite()).map(|v| (s, v)))
+        .collect();
+    series.sort_by(|a, b| b.1.total_cmp(&a.1));
+    series.truncate(panel.limit);
+    if series.is_empty() {
+        return;
+    }
+
+    let legend: Vec<Spans> = series.iter()
+        .enumerate()
+        .map(|(i, (s, v))| Spans::from(format!("{:>2} {}  {}", i + 1, s.name, panel.format.format(*v))))
+        .collect();
+    let legend_width = legend.iter().map(|l| l.width()).max().unwrap_or(0) as u16;
+    let parts = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Min(1), Constraint::Length(legend_width.min(inner.width / 2))])
+        .split(inner);
+
+    // bars only take whole numbers, so scale so the largest value is BAR_SCALE
+    let max = series[0].1.max(0.0);
+    let labels: Vec<String> = (1..=series.len()).map(|i| i.to_string()).collect();
+    let data: Vec<(&str, u64)> = series.iter()
+        .zip(labels.iter())
+        .map(|((_, v), l)| match max > 0.0 {
+            true => (l.as_str(), (v.max(0.0) / max * BAR_SCALE as f64).round() as u64),
+            false => (l.as_str(), 0)
+        })
+        .collect();
+    let bar_width = (parts[0].width / series.len() as u16).saturating_sub(1).clamp(1, BAR_MAX_WIDTH);
+    let chart = BarChart::default()
+        .data(&data)
+        .max(BAR_SCALE)
+        .bar_width(bar_width)
+        .bar_gap(1)
+        .bar_style(Style::default().fg(Color::Cyan))
+        // the scaled value means nothing to the reader, so paint it over in the bar's color
+        .value_style(Style::default().fg(Color::Cyan).bg(Color::Cyan))
+        .label_style(Style::default().fg(Color::Gray));
+    f.render_widget(chart, parts[0]);
+    f.render_widget(Paragraph::new(legend), parts[1]);
+}
+
+// values bucketed into `width` columns across the window, scaled to the series' own range
+fn sparkline_data(series: &Series, window: (f64, f64), width: usize) -> Vec<u64> {
+    let (start, end) = window;
+    let mut columns: Vec<Option<f64>> = vec![None; width];
+    for (t, v) in series.points.iter().filter(|(_, v)| v.is_finite()) {
+        let col = ((t - start) / (end - start).max(1.0) * width as f64) as usize;
+        columns[col.min(width - 1)] = Some(*v);
+    }
+    let (lo, hi) = columns.iter()
+        .flatten()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), v| (lo.min(*v), hi.max(*v)));
+    columns.iter()
+        .map(|c| match c {
+            // a flat line still gets drawn, at half height
+            Some(v) if hi > lo => 1 + ((v - lo) / (hi - lo) * (SPARKLINE_SCALE - 1) as f64).round() as u64,
+            Some(_) => SPARKLINE_SCALE / 2,
+            None => 0
+        })
+        .collect()
+}
+
+// a row per series: its name, a sparkline over the window and its latest value
+fn draw_sparkline<B: Backend>(f: &mut Frame<B>, panel: &Panel, area: Rect, title_style: Style) {
+    let block = panel_block(&panel.title, title_style);
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+    let shown = panel.data.len().min(inner.height as usize);
+    if shown == 0 {
+        return;
+    }
+    let name_width = panel.data.iter().map(|s| s.name.chars().count()).max().unwrap_or(0) as u16;
+    let value_width = panel.data.iter().map(|s| panel.format.format_opt(s.last()).chars().count()).max().unwrap_or(0) as u16;
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(vec![Constraint::Ratio(1, shown as u32); shown])
+        .split(inner);
+    for (i, (series, row)) in panel.data.iter().zip(rows.iter()).enumerate() {
+        let color = SERIES_PALETTE[i % SERIES_PALETTE.len()];
+        let cols = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([
+                Constraint::Length(name_width.min(inner.width / 3) + 1),
+                Constraint::Min(1),
+                Constraint::Length(value_width + 1),
+            ])
+            .split(*row);
+        let data = sparkline_data(series, panel.window, cols[1].width.max(1) as usize);
+        f.render_widget(Paragraph::new(Span::styled(series.name.as_str(), Style::default().fg(color))), cols[0]);
+        f.render_widget(Sparkline::default().data(&data).max(SPARKLINE_SCALE).style(Style::default().fg(color)), cols[1]);
+        f.render_widget(Paragraph::new(panel.format.format_opt(series.last())).alignment(Alignment::Right), cols[2]);
+    }
+}
+
+// a row per series with a column per label and the latest value; s and S change the sort
+fn draw_table<B: Backend>(f: &mut Frame<B>, panel: &Panel, area: Rect, title_style: Style) {
+    let keys = panel.label_keys();
+    let arrow = match panel.sort_desc {
+        true => " ▼",
+        false => " ▲"
+    };
+    let mut header: Vec<String> = keys.iter().map(|k| k.to_string()).collect();
+    header.push("value".to_string());
+    // sort_column 0 is the value, which is drawn last; like sorted_series, a label column that went away
+    // with a refetch falls back to the value
+    let sorted = match panel.sort_column.checked_sub(1).filter(|i| *i < keys.len()) {
+        Some(i) => i,
+        None => keys.len()
+    };
+    header[sorted].push_str(arrow);
+
+    let rows: Vec<Vec<String>> = panel.sorted_series().iter()
+        .map(|s| {
+            let mut row: Vec<String> = keys.iter().map(|k| s.labels.get(*k).cloned().unwrap_or_default()).collect();
+            row.push(panel.format.format_opt(s.last()));
+            row
+        })
+        .collect();
+    let widths: Vec<Constraint> = (0..header.len())
+        .map(|i| {
+            let width = rows.iter().map(|r| r[i].chars().count()).chain([header[i].chars().count()]).max().unwrap_or(0);
+            Constraint::Length(width as u16)
+        })
+        .collect();
+    let table = Table::new(rows.into_iter().map(Row::new))
+        .header(Row::new(header).style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)))
+        .block(panel_block(&panel.title, title_style))
+        .widths(&widths)
+        .column_spacing(2);
+    f.render_widget(table, area);
+}
+//endregion
+
+//region Heatmap Panel
+fn heat_color(ratio: f64) -> Color {
+    let scaled = ratio.clamp(0.0, 1.0) * (HEATMAP_GRADIENT.len() - 1) as f64;
+    let i = (scaled.floor() as usize).min(HEATMAP_GRADIENT.len() - 2);
+    let t = scaled - i as f64;
+    let (from, to) = (HEATMAP_GRADIENT[i], HEATMAP_GRADIENT[i + 1]);
+    let mix = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * t).round() as u8;
+    Color::Rgb(mix(from.0, to.0), mix(from.1, to.1), mix(from.2, to.2))
+}
+
+fn quantile_name(q: f64) -> String {
+    format!("p{}", ValueFormat::default().format(q * 100.0))
+}
+
+// each column's bucket counts: the average of the samples inside it, or the last sample
+// before it when there are fewer samples than columns
+fn heatmap_columns(heatmap: &Heatmap, window: (f64, f64), width: u16) -> Vec<Option<Vec<f64>>> {
+    let (start, end) = window;
+    let span = (end - start) / width.max(1) as f64;
+    let step = heatmap.times.windows(2).map(|w| w[1] - w[0]).fold(f64::INFINITY, f64::min);
+    (0..width)
+        .map(|c| {
+            let (from, to) = (start + c as f64 * span, start + (c + 1) as f64 * span);
+            let inside: Vec<&Vec<f64>> = heatmap.times.iter()
+                .zip(heatmap.counts.iter())
+                .filter(|(t, _)| **t >= from && **t < to)
+                .map(|(_, counts)| counts)
+                .collect();
+            if inside.is_empty() {
+                return heatmap.times.iter()
+                    .rposition(|t| *t < from)
+                    .filter(|i| from - heatmap.times[*i] <= step)
+                    .map(|i| heatmap.counts[i].clone());
+            }
+            let mut average = vec![0.0; heatmap.upper.len()];
+            for counts in &inside {
+                for (a, c) in average.iter_mut().zip(counts.iter()) {
+                    *a += c / inside.len() as f64;
+                }
+            }
+            Some(average)
+        })
+        .collect()
+}
+
+// the buckets drawn on screen row `row` (0 at the bottom): stretched over several rows when there are
+// fewer buckets than rows, merged when there are more
+fn bucket_group(row: usize, rows: usize, buckets: usize) -> (usize, usize) {
+    let start = row * buckets / rows;
+    (start, ((row + 1) * buckets / rows).max(start + 1))
+}
+
+// time across, buckets up the side, colored by count, with the panel's quantiles drawn over the top
+fn draw_heatmap<B: Backend>(f: &mut Frame<B>, panel: &Panel, area: Rect, title_style: Style) {
+    let block = panel_block(&panel.title, title_style);
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+    let heatmap = match &panel.heatmap {
+        Some(h) if !h.is_empty() => h,
+        _ => return
+    };
+    // the legend takes the top row and the time labels the bottom one
+    if inner.height < 4 {
+        return;
+    }
+    let rows = (inner.height - 2) as usize;
+    let groups: Vec<(usize, usize)> = (0..rows).map(|r| bucket_group(r, rows, heatmap.upper.len())).collect();
+    let labels: Vec<String> = groups.iter().map(|(_, end)| panel.format.format(heatmap.upper[end - 1])).collect();
+    let label_width = labels.iter().map(|l| l.chars().count()).max().unwrap_or(0) + 1;
+    if inner.width as usize <= label_width + 1 {
+        return;
+    }
+    let width = inner.width as usize - label_width;
+
+    let columns = heatmap_columns(heatmap, panel.window, width as u16);
+    // cells[column][row], with the buckets of merged rows added together
+    let cells: Vec<Vec<f64>> = columns.iter()
+        .map(|c| match c {
+            Some(counts) => groups.iter().map(|(s, e)| counts[*s..*e].iter().sum()).collect(),
+            None => vec![0.0; rows]
+        })
+        .collect();
+    let max = cells.iter().flatten().fold(0.0_f64, |m, v| m.max(*v));
+    let marks: Vec<Vec<Option<usize>>> = columns.iter()
+        .map(|c| {
+            let mut marks = vec![None; rows];
+            if let Some(counts) = c {
+                for (i, q) in panel.quantiles.iter().enumerate() {
+                    if let Some((position, _)) = heatmap.quantile(counts, *q) {
+                        let row = (position * rows as f64 / heatmap.upper.len() as f64) as usize;
+                        marks[row.min(rows - 1)] = Some(i);
+                    }
+                }
+            }
+            marks
+        })
+        .collect();
+
+    // gradient scale, then each quantile with its latest value
+    let mut legend = vec![Span::raw(format!("{:w$}0 ", "", w = label_width))];
+    legend.extend((0..HEATMAP_LEGEND_WIDTH).map(|i| {
+        Span::styled(" ", Style::default().bg(heat_color(i as f64 / (HEATMAP_LEGEND_WIDTH - 1) as f64)))
+    }));
+    legend.push(Span::raw(format!(" {}", ValueFormat::default().format(max))));
+    let latest = columns.iter().rev().flatten().next();
+    for (i, q) in panel.quantiles.iter().enumerate() {
+        let value = latest.and_then(|c| heatmap.quantile(c, *q)).map(|(_, v)| v);
+        legend.push(Span::styled("  • ", Style::default().fg(QUANTILE_PALETTE[i % QUANTILE_PALETTE.len()])));
+        legend.push(Span::raw(format!("{} {}", quantile_name(*q), panel.format.format_opt(value))));
+    }
+
+    let mut lines = vec![Spans::from(legend)];
+    for row in (0..rows).rev() {
+        let mut spans = vec![Span::styled(format!("{:>w$} ", labels[row], w = label_width - 1), Style::default().fg(Color::Gray))];
+        for (cell, mark) in cells.iter().zip(marks.iter()) {
+            let style = match cell[row] > 0.0 {
+                true => Style::default().bg(heat_color(cell[row] / max)),
+                false => Style::default()
+            };
+            spans.push(match mark[row] {
+                Some(i) => Span::styled("•", style.fg(QUANTILE_PALETTE[i % QUANTILE_PALETTE.len()]).add_modifier(Modifier::BOLD)),
+                None => Span::styled(" ", style)
+            });
+        }
+        lines.push(Spans::from(spans));
+    }
+
+    // time labels at the start, middle and end of the window
+    let (start, end) = panel.window;
+    let ticks: Vec<String> = [start, (start + end) / 2.0, end].iter().map(|t| format_tick(*t, end - start)).collect();
+    let left = width.saturating_sub(ticks[1].len()) / 2;
+    let right = width.saturating_sub(left + ticks[1].len());
+    lines.push(Spans::from(format!("{:w$}{:<l$}{}{:>r$}", "", ticks[0], ticks[1], ticks[2], w = label_width, l = left, r = right)));
+    f.render_widget(Paragraph::new(lines), inner);
+}
+//endregion
+
+// a threshold drawn as a horizontal line across the chart
+struct ThresholdLine<'a> {
+    threshold: &'a ThresholdRef,
+    color: Color,
+    points: [(f64, f64); 2],
+}
+
+// a stretch of a series above a threshold, redrawn in the threshold's color
+struct Overlay {
+    level: f64,
+    color: Color,
+    points: Vec<(f64, f64)>,
+}
+
+// the stretches of a series at or above `level`, each starting and ending where the line crosses it
+fn runs_above(points: &[(f64, f64)], level: f64) -> Vec<Vec<(f64, f64)>> {
+    let mut runs = vec![];
+    let mut run: Vec<(f64, f64)> = vec![];
+    for (i, (t, v)) in points.iter().enumerate() {
+        let above = *v >= level;
+        if let Some((pt, pv)) = i.checked_sub(1).map(|p| points[p]) {
+            if above != (pv >= level) && pv.is_finite() && v.is_finite() {
+                run.push((pt + (t - pt) * (level - pv) / (v - pv), level));
+            }
+        }
+        if above {
+            run.push((*t, *v));
+        } else if !run.is_empty() {
+            runs.push(std::mem::take(&mut run));
+        }
+    }
+    if !run.is_empty() {
+        runs.push(run);
+    }
+    runs
+}
+
+// `markers` are the times alerts on the panel's server started firing, drawn as vertical lines
+fn draw_graph<B: Backend>(f: &mut Frame<B>, panel: &mut Panel, area: Rect, cursor: Option<f64>, markers: &[f64], focused: bool, title_style: Style) {
+    let (time_start, time_end) = panel.window;
+    let (y_min, y_max) = y_bounds(&panel.data, panel.thresholds.iter().map(|t| t.value));
+    let y_labels = axis_labels(y_min, y_max, CHART_Y_LABEL_COUNT, |v| panel.format.format(v));
+    let crosshair: Vec<(f64, f64)> = match cursor {
+        Some(c) => vec![(c, y_min), (c, y_max)],
+        None => vec![]
+    };
+    let thresholds: Vec<ThresholdLine> = panel.thresholds.iter()
+        .map(|t| ThresholdLine {
+            threshold: t,
+            color: t.color().unwrap_or(Color::Red),
+            points: [(time_start, t.value), (time_end, t.value)],
+        })
+        .collect();
+    // higher thresholds are drawn last so their color wins where they overlap
+    let mut overlays: Vec<Overlay> = match panel.color_above {
+        true => thresholds.iter()
+            .flat_map(|line| panel.data.iter()
+                .flat_map(|s| runs_above(&s.points, line.threshold.value))
+                .map(|run| Overlay { level: line.threshold.value, color: line.color, points: run }))
+            .collect(),
+        false => vec![]
+    };
+    overlays.sort_by(|a, b| a.level.total_cmp(&b.level));
+    let marker_lines: Vec<[(f64, f64); 2]> = markers.iter()
+        .filter(|t| **t >= time_start && **t <= time_end)
+        .map(|t| [(*t, y_min), (*t, y_max)])
+        .collect();
+
+    let mut datasets: Vec<Dataset> = panel.data.iter()
+        .enumerate()
+        .map(|(i, series)| Dataset::default()
+            .name(series.name.as_str())
+            .graph_type(GraphType::Line)
+            .marker(symbols::Marker::Braille)
+            .style(Style::default().fg(SERIES_PALETTE[i % SERIES_PALETTE.len()]))
+            .data(&series.points))
+        .collect();
+    for overlay in &overlays {
+        datasets.push(Dataset::default()
+            .graph_type(GraphType::Line)
+            .marker(symbols::Marker::Braille)
+            .style(Style::default().fg(overlay.color))
+            .data(&overlay.points));
+    }
+    for line in &thresholds {
+        let dataset = Dataset::default()
+            .graph_type(GraphType::Line)
+            .marker(symbols::Marker::Braille)
+            .style(Style::default().fg(line.color))
+            .data(&line.points);
+        datasets.push(match &line.threshold.label {
+            Some(label) => dataset.name(label.as_str()),
+            None => dataset
+        });
+    }
+    for line in &marker_lines {
+        datasets.push(Dataset::default()
+            .graph_type(GraphType::Line)
+            .marker(symbols::Marker::Braille)
+            .style(Style::default().fg(ALERT_MARKER_COLOR))
+            .data(line));
+    }
+    if !crosshair.is_empty() {
+        datasets.push(Dataset::default()
+            .graph_type(GraphType::Line)
+            .marker(symbols::Marker::Braille)
+            .style(Style::default().fg(Color::White))
+            .data(&crosshair));
+    }
+    let chart = Chart::new(datasets)
+        .block(
+            Block::default()
+                .title(Span::styled(panel.title.as_str(), title_style))
+                .borders(Borders::ALL),
+        )
+        .hidden_legend_constraints((Constraint::Ratio(1, 2), Constraint::Ratio(1, 2)))
+        .x_axis(
+            Axis::default()
+                .style(Style::default().fg(Color::Gray))
+                .labels(axis_labels(time_start, time_end, CHART_X_LABEL_COUNT, |t| format_tick(t, time_end - time_start)))
+                .bounds([time_start, time_end]),
+        )
+        .y_axis(
+            Axis::default()
+                .style(Style::default().fg(Color::Gray))
+                .labels(y_labels.clone())
+                .bounds([y_min, y_max]),
+        );
+    let plot = plot_area(area, &y_labels);
+    f.render_widget(chart, area);
+    panel.plot_area = plot;
+    render_threshold_labels(f, panel, plot, (y_min, y_max));
+    if let (Some(c), true) = (cursor, focused) {
+        render_cursor_popup(f, panel, c, area);
+    }
+}
+
+// each threshold's label and value, just above its line at the right of the plot
+fn render_threshold_labels<B: Backend>(f: &mut Frame<B>, panel: &Panel, plot: Rect, (y_min, y_max): (f64, f64)) {
+    if plot.height < 2 || plot.width == 0 {
+        return;
+    }
+    for t in &panel.thresholds {
+        let from_top = (y_max - t.value) / (y_max - y_min) * (plot.height - 1) as f64;
+        let row = (from_top.round() as u16).saturating_sub(1).min(plot.height - 1);
+        let text = match &t.label {
+            Some(label) => format!("{} {}", label, panel.format.format(t.value)),
+            None => panel.format.format(t.value)
+        };
+        let width = (text.chars().count() as u16).min(plot.width);
+        let style = Style::default().fg(t.color().unwrap_or(Color::Red));
+        f.render_widget(Paragraph::new(Span::styled(text, style)), Rect::new(plot.right() - width, plot.y + row, width, 1));
+    }
+}
+
+fn alert_badge(state: AlertState) -> Span<'static> {
+    match state {
+        AlertState::Firing => Span::styled(" FIRING ", Style::default().bg(Color::Red).fg(Color::White).add_modifier(Modifier::BOLD)),
+        AlertState::Pending => Span::styled(" PENDING ", Style::default().bg(Color::Yellow).fg(Color::Black).add_modifier(Modifier::BOLD)),
+        AlertState::Inactive => Span::raw(" inactive "),
+    }
+}
+
+// one entry per alert: state, name and age, then its labels, annotations and the rule's expression
+fn alert_item<'a>(alert: &'a ActiveAlert, show_server: bool, width: u16) -> ListItem<'a> {
+    let now = now_secs();
+    let since = match alert.active_since {
+        Some(t) => format!("  since {} ({} ago)",
+                           format_tick(t, now - t),
+                           humantime::format_duration(Duration::from_secs((now - t).max(0.0) as u64))),
+        None => "".to_string()
+    };
+    let mut header = vec![
+        alert_badge(alert.state),
+        Span::styled(format!(" {}", alert.name), Style::default().add_modifier(Modifier::BOLD)),
+        Span::raw(since),
+    ];
+    if !alert.value.is_empty() {
+        header.push(Span::styled(format!("  value {}", alert.value), Style::default().fg(Color::DarkGray)));
+    }
+    if show_server {
+        header.push(Span::styled(format!("  @ {}", alert.server), Style::default().fg(Color::DarkGray)));
+    }
+    let dim = Style::default().fg(Color::DarkGray);
+    let labels: Labels = alert.labels.iter()
+        .filter(|(k, _)| k.as_str() != "alertname")
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    let mut lines = vec![Spans::from(header)];
+    if !labels.is_empty() {
+        lines.push(Spans::from(Span::raw(format!("  {}", format_labels(&labels)))));
+    }
+    for (key, value) in &alert.annotations {
+        lines.push(Spans::from(vec![
+            Span::styled(format!("  {}: ", key), dim),
+            Span::raw(value.split_whitespace().collect::<Vec<_>>().join(" ")),
+        ]));
+    }
+    let expr: String = alert.expr.split_whitespace().collect::<Vec<_>>().join(" ");
+    let expr = match expr.chars().count() > width as usize {
+        true => format!("{}…", expr.chars().take(width.saturating_sub(1) as usize).collect::<String>()),
+        false => expr
+    };
+    lines.push(Spans::from(vec![Span::styled("  expr: ", dim), Span::styled(expr, dim)]));
+    ListItem::new(lines)
+}
+
+// firing and pending alerts from every server the panels query, over the panel grid
+fn render_alerts<B: Backend>(f: &mut Frame<B>, app: &AppData, area: Rect) {
+    let row = match app.alerts_view {
+        Some(row) => row,
+        None => return
+    };
+    let alerts = app.active_alerts();
+    let firing = alerts.iter().filter(|a| a.state == AlertState::Firing).count();
+    let title = format!("Alerts: {} firing, {} pending  ↑/↓ move  esc close", firing, alerts.len() - firing);
+    let show_server = app.alerts.len() > 1;
+    // room for the expression beside the border, indent and "expr: "
+    let width = area.width.saturating_sub(12);
+    let mut items: Vec<ListItem> = app.alerts.iter()
+        .filter_map(|(server, r)| r.as_ref().err().map(|e| (server, e)))
+        .map(|(server, e)| ListItem::new(Span::styled(format!("{}: {}", server, e), Style::default().fg(Color::Red))))
+        .collect();
+    let errors = items.len();
+    items.extend(alerts.iter().map(|a| alert_item(a, show_server, width)));
+    if items.is_empty() {
+        items.push(ListItem::new(match app.alerts.is_empty() {
+            true => "loading…",
+            false => "No alerts are firing or pending"
+        }));
+    }
+    let popup = Rect::new(area.x + 1, area.y + 1, area.width.saturating_sub(2), area.height.saturating_sub(2));
+    let mut state = ListState::default();
+    if !alerts.is_empty() {
+        state.select(Some(errors + row));
+    }
+    let list = List::new(items)
+        .block(create_dialog_block(&title))
+        .highlight_style(Style::default().bg(Color::DarkGray));
+    f.render_widget(Clear, popup);
+    f.render_stateful_widget(list, popup, &mut state);
+}
+
+// dropdown for the variable being edited; the other variables are listed in its title
+fn render_picker<B: Backend>(f: &mut Frame<B>, app: &AppData, area: Rect) {
+    let picker = match &app.picker {
+        Some(p) => p,
+        None => return
+    };
+    let variable = &app.variables[picker.var];
+    let items: Vec<ListItem> = match (&variable.options, &variable.error) {
+        (_, Some(e)) => vec![ListItem::new(Span::styled(e.as_str(), Style::default().fg(Color::Red)))],
+        (None, None) => vec![ListItem::new("loading…")],
+        (Some(_), None) => variable.choices().iter()
+            .map(|c| {
+                let mark = match (variable.is_chosen(c), variable.def.multi) {
+                    (true, true) => "[x] ",
+                    (false, true) => "[ ] ",
+                    (true, false) => "(•) ",
+                    (false, false) => "( ) ",
+                };
+                ListItem::new(format!("{}{}", mark, c))
+            })
+            .collect()
+    };
+    let title = itertools::join(
+        app.variables.iter().enumerate().map(|(i, v)| match i == picker.var {
+            true => format!("[{}]", v.def.name),
+            false => v.def.name.clone()
+        }),
+        " ");
+    let width = (area.width / 2).max(20).min(area.width);
+    let height = (items.len() as u16 + 2).min(area.height.saturating_sub(1));
+    let popup = Rect::new(area.x + (area.width - width) / 2, area.y + 1, width, height);
+    let mut state = ListState::default();
+    state.select(Some(picker.row));
+    let list = List::new(items)
+        .block(create_dialog_block(&title))
+        .highlight_style(Style::default().bg(Color::DarkGray).add_modifier(Modifier::BOLD));
+    f.render_widget(Clear, popup);
+    f.render_stateful_widget(list, popup, &mut state);
+}
+
+// query input bar along the bottom of the panel grid, growing with the expression's line count
+fn render_editor<B: Backend>(f: &mut Frame<B>, app: &AppData, area: Rect) {
+    let editor = match &app.editor {
+        Some(e) => e,
+        None => return
+    };
+    let (title, text) = match &editor.mode {
+        EditorMode::Edit => (
+            "PromQL  enter run  tab complete  alt-enter newline  ↑/↓ history  ^R search  ^S save  esc close".to_string(),
+            app.query.as_str(),
+        ),
+        EditorMode::Search { term, hit } => (
+            match hit {
+                Some(_) => format!("(reverse-i-search)`{}'  ^R older  enter accept  esc back", term),
+                None => format!("(failed reverse-i-search)`{}'  esc back", term)
+            },
+            editor.search_hit().unwrap_or(app.query.as_str()),
+        ),
+        EditorMode::Save { name } => (
+            format!("Save as query: {}▏  enter save  esc back", name),
+            app.query.as_str(),
+        ),
+    };
+    let lines = text.split('\n').count() as u16;
+    let height = (lines + 2).min(area.height / 2).max(3).min(area.height);
+    let popup = Rect::new(area.x, area.y + area.height - height, area.width, height);
+    let inner_width = popup.width.saturating_sub(2);
+    let inner_height = popup.height.saturating_sub(2);
+
+    // scroll so the cursor stays inside the box
+    let (row, col) = editor.position(&app.query);
+    let scroll = match editor.mode {
+        EditorMode::Edit => (
+            (row as u16).saturating_sub(inner_height.saturating_sub(1)),
+            (col as u16).saturating_sub(inner_width.saturating_sub(1)),
+        ),
+        _ => (0, 0)
+    };
+    let paragraph = Paragraph::new(text)
+        .block(create_dialog_block(&title))
+        .scroll(scroll);
+    f.render_widget(Clear, popup);
+    f.render_widget(paragraph, popup);
+    if let EditorMode::Edit = editor.mode {
+        let cursor_x = popup.x + 1 + col as u16 - scroll.1;
+        f.set_cursor(cursor_x, popup.y + 1 + row as u16 - scroll.0);
+        render_completions(f, app, Rect::new(area.x, area.y, area.width, popup.y - area.y), cursor_x);
+    }
+}
+
+// candidates above the input bar, starting at the cursor; the highlighted one's help is the title
+fn render_completions<B: Backend>(f: &mut Frame<B>, app: &AppData, area: Rect, cursor_x: u16) {
+    let popup = match app.editor.as_ref().and_then(|e| e.completion.as_ref()) {
+        Some(c) => c,
+        None => return
+    };
+    let items: Vec<ListItem> = match popup.items.is_empty() {
+        true if popup.loading => vec![ListItem::new("loading…")],
+        true => match &popup.error {
+            Some(e) => vec![ListItem::new(Span::styled(e.as_str(), Style::default().fg(Color::Red)))],
+            None => vec![ListItem::new("no completions")]
+        },
+        false => popup.items.iter()
+            .map(|c| ListItem::new(Spans::from(vec![
+                Span::styled(format!("{:<7}", c.kind.tag()), Style::default().fg(Color::Blue)),
+                Span::raw(c.text.as_str()),
+            ])))
+            .collect()
+    };
+    let title = match popup.items.get(popup.selected) {
+        Some(c) => c.detail.clone(),
+        None => "".to_string()
+    };
+    let longest = popup.items.iter().map(|c| c.text.chars().count()).max().unwrap_or(16) as u16;
+    let width = (longest + 10).max(title.chars().count().min(60) as u16 + 4).min(area.width);
+    let height = (items.len() as u16 + 2).min(area.height).min(COMPLETION_POPUP_HEIGHT);
+    if height < 3 {
+        return;
+    }
+    let x = cursor_x.min(area.x + area.width - width);
+    let rect = Rect::new(x, area.y + area.height - height, width, height);
+    let mut state = ListState::default();
+    state.select(Some(popup.selected));
+    let list = List::new(items)
+        .block(create_dialog_block(&title))
+        .highlight_style(Style::default().bg(Color::DarkGray).add_modifier(Modifier::BOLD));
+    f.render_widget(Clear, rect);
+    f.render_stateful_widget(list, rect, &mut state);
+}
+
+// approximates the chart's inner graph area: inside the border, right of the y labels, above the x labels
+fn plot_area(area: Rect, y_labels: &[Span]) -> Rect {
+    let label_width = y_labels.iter().map(|l| l.width()).max().unwrap_or(0) as u16 + 1;
+    let inner = Rect::new(area.x + 1, area.y + 1, area.width.saturating_sub(2), area.height.saturating_sub(2));
+    Rect::new(
+        inner.x + label_width,
+        inner.y,
+        inner.width.saturating_sub(label_width),
+        inner.height.saturating_sub(2),
+    )
+}
+
+fn render_cursor_popup<B: Backend>(f: &mut Frame<B>, panel: &Panel, c: f64, area: Rect) {
+    let mut lines = vec![Spans::from(Span::styled(
+        format_tick(c, 0.0),
+        Style::default().add_modifier(Modifier::BOLD),
+    ))];
+    for (i, name, value) in panel.cursor_values(c) {
+        let value = panel.format.format_opt(value);
+        lines.push(Spans::from(vec![
+            Span::styled("■ ", Style::default().fg(SERIES_PALETTE[i % SERIES_PALETTE.len()])),
+            Span::raw(format!("{}: {}", name, value)),
+        ]));
+    }
+    let width = lines.iter().map(|l| l.width()).max().unwrap_or(0) as u16 + 2;
+    let height = lines.len() as u16 + 2;
+    let popup = Rect::new(
+        area.x + 2,
+        area.y + 1,
+        width.min(area.width.saturating_sub(4)),
+        height.min(area.height.saturating_sub(2)),
+    );
+    f.render_widget(Clear, popup);
+    f.render_widget(Paragraph::new(lines).block(create_dialog_block("Cursor")), popup);
+}
+
+// min/max of the finite values in view and any threshold lines, padded so lines don't hug the chart border
+fn y_bounds(data: &[Series], thresholds: impl Iterator<Item=f64>) -> (f64, f64) {
+    let (min, max) = data.iter()
+        .flat_map(|s| s.points.iter())
+        .map(|(_, v)| *v)
+        .chain(thresholds)
+        .filter(|v| v.is_finite())
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), v| (lo.min(v), hi.max(v)));
+    if !min.is_finite() {
+        return (0.0, 1.0);
+    }
+    let pad = match max - min {
+        d if d > 0.0 => d * CHART_Y_PADDING_RATIO,
+        _ => (min.abs() * CHART_Y_PADDING_RATIO).max(1.0)
+    };
+    (min - pad, max + pad)
+}
+
+fn axis_labels<'a>(lo: f64, hi: f64, count: usize, fmt: impl Fn(f64) -> String) -> Vec<Span<'a>> {
+    (0..count)
+        .map(|i| lo + (hi - lo) * i as f64 / (count - 1) as f64)
+        .enumerate()
+        .map(|(i, v)| match i {
+            i if i == 0 || i == count - 1 => Span::styled(fmt(v), Style::default().add_modifier(Modifier::BOLD)),
+            _ => Span::raw(fmt(v))
+        })
+        .collect()
+}
+
 fn create_block(title: &str) -> Block {
     Block::default()
         .borders(Borders::ALL)
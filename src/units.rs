@@ -0,0 +1,202 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+use crate::consts::*;
+
+// Value units, named as grafana names them so existing dashboards translate directly.
+#[derive(Deserialize, ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Unit {
+    /// Plain numbers
+    #[default]
+    None,
+    /// SI suffixes: 1.2k, 3.4M, 5.6G
+    Short,
+    /// Bytes with IEC prefixes: KiB, MiB, GiB
+    Bytes,
+    /// Bytes with SI prefixes: kB, MB, GB
+    Decbytes,
+    /// Bits per second: kb/s, Mb/s, Gb/s
+    #[serde(rename = "bps")]
+    #[value(name = "bps")]
+    Bits,
+    /// Operations per second
+    Ops,
+    /// Durations given in seconds
+    #[serde(rename = "s", alias = "seconds")]
+    #[value(name = "s", alias = "seconds")]
+    Seconds,
+    /// Durations given in milliseconds
+    #[serde(rename = "ms", alias = "milliseconds")]
+    #[value(name = "ms", alias = "milliseconds")]
+    Milliseconds,
+    /// Percentages from 0 to 100
+    Percent,
+    /// Ratios from 0 to 1, shown as percentages
+    Percentunit,
+}
+
+const SI_PREFIXES: [&str; 7] = ["", "k", "M", "G", "T", "P", "E"];
+const IEC_PREFIXES: [&str; 7] = ["", "Ki", "Mi", "Gi", "Ti", "Pi", "Ei"];
+// (seconds in the unit, its suffix), smallest first
+const DURATIONS: [(f64, &str); 8] = [
+    (1e-9, "ns"),
+    (1e-6, "µs"),
+    (1e-3, "ms"),
+    (1.0, "s"),
+    (60.0, "m"),
+    (3600.0, "h"),
+    (86400.0, "d"),
+    (604800.0, "w"),
+];
+
+// How a panel or query shows its values: a unit, and a fixed number of decimals or None for about three
+// significant digits with trailing zeros dropped.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ValueFormat {
+    pub unit: Unit,
+    pub decimals: Option<usize>,
+}
+
+impl ValueFormat {
+    pub fn new(unit: Option<Unit>, decimals: Option<usize>) -> Self {
+        ValueFormat { unit: unit.unwrap_or_default(), decimals }
+    }
+
+    pub fn format(&self, v: f64) -> String {
+        if v.is_nan() {
+            return "NaN".to_string();
+        }
+        if v.is_infinite() {
+            return if v > 0.0 { "+Inf" } else { "-Inf" }.to_string();
+        }
+        match self.unit {
+            Unit::None => self.number(v),
+            Unit::Short => self.scaled(v, 1000.0, &SI_PREFIXES, ""),
+            Unit::Bytes => self.scaled(v, 1024.0, &IEC_PREFIXES, "B"),
+            Unit::Decbytes => self.scaled(v, 1000.0, &SI_PREFIXES, "B"),
+            Unit::Bits => self.scaled(v, 1000.0, &SI_PREFIXES, "b/s"),
+            Unit::Ops => format!("{} ops/s", self.scaled(v, 1000.0, &SI_PREFIXES, "")),
+            Unit::Seconds => self.duration(v),
+            Unit::Milliseconds => self.duration(v / 1000.0),
+            Unit::Percent => format!("{}%", self.number(v)),
+            Unit::Percentunit => format!("{}%", self.number(v * 100.0)),
+        }
+    }
+
+    pub fn format_opt(&self, v: Option<f64>) -> String {
+        match v {
+            Some(v) => self.format(v),
+            None => "-".to_string()
+        }
+    }
+
+    fn number(&self, v: f64) -> String {
+        match self.decimals {
+            Some(d) => format!("{:.*}", d, v),
+            None => {
+                let text = format!("{:.*}", auto_decimals(v), v);
+                match text.contains('.') {
+                    true => text.trim_end_matches('0').trim_end_matches('.').to_string(),
+                    false => text
+                }
+            }
+        }
+    }
+
+    // divides by `base` until the value is below it, e.g. 1536 bytes is "1.5 KiB"
+    fn scaled(&self, v: f64, base: f64, prefixes: &[&str], suffix: &str) -> String {
+        let mut scaled = v;
+        let mut i = 0;
+        while scaled.abs() >= base && i + 1 < prefixes.len() {
+            scaled /= base;
+            i += 1;
+        }
+        // short numbers are written 1.2k, units with a space as 1.2 KiB
+        match suffix.is_empty() {
+            true => format!("{}{}", self.number(scaled), prefixes[i]),
+            false => format!("{} {}{}", self.number(scaled), prefixes[i], suffix)
+        }
+    }
+
+    // the largest unit the duration is at least one of, e.g. 0.25 is "250 ms" and 5400 is "1.5 h"
+    fn duration(&self, secs: f64) -> String {
+        if secs == 0.0 {
+            return format!("{} s", self.number(0.0));
+        }
+        let (size, suffix) = DURATIONS.iter()
+            .rev()
+            .find(|(size, _)| secs.abs() >= *size)
+            .unwrap_or(&DURATIONS[0]);
+        format!("{} {}", self.number(secs / size), suffix)
+    }
+}
+
+// enough decimals for three significant digits: 123, 12.3, 1.23, 0.0123
+fn auto_decimals(v: f64) -> usize {
+    match v.abs() {
+        a if a == 0.0 || a >= 100.0 => 0,
+        a => (2 - a.log10().floor() as i32).clamp(0, MAX_AUTO_DECIMALS) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fmt(unit: Unit, v: f64) -> String {
+        ValueFormat::new(Some(unit), None).format(v)
+    }
+
+    #[test]
+    fn auto_decimals_keep_three_significant_digits() {
+        assert_eq!(fmt(Unit::None, 1234.5678), "1235");
+        assert_eq!(fmt(Unit::None, 100.0), "100");
+        assert_eq!(fmt(Unit::None, 1.5), "1.5");
+        assert_eq!(fmt(Unit::None, 0.012345), "0.0123");
+        assert_eq!(fmt(Unit::None, 0.0), "0");
+        assert_eq!(fmt(Unit::None, -2.25), "-2.25");
+    }
+
+    #[test]
+    fn fixed_decimals() {
+        assert_eq!(ValueFormat::new(None, Some(2)).format(1.0), "1.00");
+        assert_eq!(ValueFormat::new(Some(Unit::Percent), Some(0)).format(99.5), "100%");
+        assert_eq!(ValueFormat::new(Some(Unit::Bytes), Some(1)).format(1024.0), "1.0 KiB");
+    }
+
+    #[test]
+    fn special_values() {
+        assert_eq!(fmt(Unit::Bytes, f64::NAN), "NaN");
+        assert_eq!(fmt(Unit::Seconds, f64::INFINITY), "+Inf");
+        assert_eq!(fmt(Unit::Short, f64::NEG_INFINITY), "-Inf");
+        assert_eq!(ValueFormat::default().format_opt(None), "-");
+    }
+
+    #[test]
+    fn scaled_units() {
+        assert_eq!(fmt(Unit::Short, 999.0), "999");
+        assert_eq!(fmt(Unit::Short, 1500.0), "1.5k");
+        assert_eq!(fmt(Unit::Short, -1500.0), "-1.5k");
+        assert_eq!(fmt(Unit::Bytes, 1536.0), "1.5 KiB");
+        assert_eq!(fmt(Unit::Bytes, 1024.0 * 1024.0), "1 MiB");
+        assert_eq!(fmt(Unit::Decbytes, 1500.0), "1.5 kB");
+        assert_eq!(fmt(Unit::Bits, 2e6), "2 Mb/s");
+        assert_eq!(fmt(Unit::Ops, 1500.0), "1.5k ops/s");
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(fmt(Unit::Seconds, 0.0), "0 s");
+        assert_eq!(fmt(Unit::Seconds, 0.25), "250 ms");
+        assert_eq!(fmt(Unit::Seconds, 90.0), "1.5 m");
+        assert_eq!(fmt(Unit::Seconds, 5400.0), "1.5 h");
+        assert_eq!(fmt(Unit::Seconds, 2e-6), "2 µs");
+        assert_eq!(fmt(Unit::Milliseconds, 250.0), "250 ms");
+    }
+
+    #[test]
+    fn percentages() {
+        assert_eq!(fmt(Unit::Percent, 12.345), "12.3%");
+        assert_eq!(fmt(Unit::Percentunit, 0.5), "50%");
+    }
+}
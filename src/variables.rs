@@ -0,0 +1,220 @@
+use std::collections::BTreeSet;
+use crate::cfg_file::VariableRef;
+use crate::consts::*;
+
+pub struct Variable {
+    pub def: VariableRef,
+    // None until the options have been fetched from the server
+    pub options: Option<Vec<String>>,
+    pub selected: BTreeSet<String>,
+    pub all: bool,
+    pub error: Option<String>,
+}
+
+impl Variable {
+    pub fn new(def: VariableRef) -> Self {
+        Variable { def, options: None, selected: BTreeSet::new(), all: false, error: None }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.options.is_some() || self.error.is_some()
+    }
+
+    // Keeps whatever is still selectable, otherwise falls back to the default, "All", or the first option.
+    pub fn set_options(&mut self, options: Vec<String>) {
+        self.selected.retain(|s| options.contains(s));
+        if self.selected.is_empty() && !self.all {
+            // defaults are matched literally, not as regexes
+            if let Some(d) = self.def.default.as_ref().filter(|d| *d != ALL_OPTION && !options.contains(d)) {
+                warn!("variable {}: '{}' is not one of its values, so it can't be preselected", self.def.name, d);
+            }
+            match &self.def.default {
+                Some(d) if d == ALL_OPTION => self.all = self.def.include_all,
+                Some(d) if options.contains(d) => { self.selected.insert(d.clone()); }
+                _ if self.def.include_all => self.all = true,
+                _ => { self.selected.extend(options.first().cloned()); }
+            }
+        }
+        self.options = Some(options);
+        self.error = None;
+    }
+
+    // picker rows: "All" first when enabled, then the discovered options
+    pub fn choices(&self) -> Vec<String> {
+        let mut retval = vec![];
+        if self.def.include_all {
+            retval.push(ALL_OPTION.to_string());
+        }
+        retval.extend(self.options.iter().flatten().cloned());
+        retval
+    }
+
+    pub fn is_chosen(&self, choice: &str) -> bool {
+        match choice == ALL_OPTION && self.def.include_all {
+            true => self.all,
+            false => !self.all && self.selected.contains(choice)
+        }
+    }
+
+    // single-select variables replace the selection; multi-select ones toggle
+    pub fn toggle(&mut self, choice: &str) {
+        let is_all = choice == ALL_OPTION && self.def.include_all;
+        if is_all {
+            self.all = !self.all || !self.def.multi;
+            self.selected.clear();
+        } else {
+            self.all = false;
+            if !self.def.multi {
+                self.selected.clear();
+                self.selected.insert(choice.to_string());
+            } else if !self.selected.remove(choice) {
+                self.selected.insert(choice.to_string());
+            }
+        }
+        // an empty selection would render as () and match nothing
+        if !self.all && self.selected.is_empty() {
+            match is_all {
+                // turning All off picks the first option
+                true => self.selected.extend(self.options.iter().flatten().next().cloned()),
+                // deselecting the last option goes back to All, or keeps it when there is no All
+                false if self.def.include_all => self.all = true,
+                false => { self.selected.insert(choice.to_string()); }
+            }
+        }
+    }
+
+    // the string substituted for {{name}} in panel queries, regex-escaped for =~ matchers
+    pub fn value(&self) -> String {
+        // a multi-select with no options to choose from matches everything rather than nothing
+        if self.all || (self.def.multi && self.selected.is_empty()) {
+            return self.def.all_value.clone().unwrap_or_else(|| DEFAULT_ALL_VALUE.to_string());
+        }
+        match self.def.multi {
+            false => self.selected.iter().next().map(|s| regex_escape(s)).unwrap_or_default(),
+            true => format!("({})", itertools::join(self.selected.iter().map(|s| regex_escape(s)), "|"))
+        }
+    }
+
+    pub fn label(&self) -> String {
+        if self.all {
+            return format!("{}: {}", self.def.name, ALL_OPTION);
+        }
+        match self.options {
+            None if self.error.is_none() => format!("{}: …", self.def.name),
+            _ => format!("{}: {}", self.def.name, itertools::join(self.selected.iter(), ","))
+        }
+    }
+}
+
+// label_values(label) or label_values(selector, label) -> (selector, label)
+pub fn parse_label_values(query: &str) -> Result<(Option<String>, String), String> {
+    let inner = query.trim()
+        .strip_prefix("label_values(")
+        .and_then(|q| q.strip_suffix(')'))
+        .ok_or_else(|| format!("'{}' is not a label_values(...) expression", query))?;
+    match inner.rsplit_once(',') {
+        Some((selector, label)) => Ok((Some(selector.trim().to_string()), label.trim().to_string())),
+        None => Ok((None, inner.trim().to_string()))
+    }
+}
+
+// escapes regex metacharacters, doubled up because the result lands inside a PromQL string literal
+pub fn regex_escape(s: &str) -> String {
+    let mut retval = String::with_capacity(s.len());
+    for c in s.chars() {
+        if "\\.+*?()|[]{}^$".contains(c) {
+            retval.push_str("\\\\");
+        }
+        retval.push(c);
+    }
+    retval
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variable(multi: bool, include_all: bool) -> Variable {
+        Variable::new(VariableRef {
+            name: "pod".to_string(),
+            query: "label_values(pod)".to_string(),
+            server: None,
+            multi,
+            include_all,
+            all_value: None,
+            default: None,
+        })
+    }
+
+    #[test]
+    fn regex_escape_doubles_backslashes_for_string_literals() {
+        assert_eq!(regex_escape("api"), "api");
+        assert_eq!(regex_escape("api-v1.prod"), "api-v1\\\\.prod");
+        assert_eq!(regex_escape("a(b)|c*"), "a\\\\(b\\\\)\\\\|c\\\\*");
+        assert_eq!(regex_escape("é.é"), "é\\\\.é");
+    }
+
+    #[test]
+    fn single_and_multi_values_are_escaped_alike() {
+        let mut single = variable(false, false);
+        single.set_options(vec!["a.b".to_string(), "c".to_string()]);
+        assert_eq!(single.value(), "a\\\\.b");
+
+        let mut multi = variable(true, false);
+        multi.set_options(vec!["a.b".to_string(), "c".to_string()]);
+        multi.toggle("c");
+        assert_eq!(multi.value(), "(a\\\\.b|c)");
+    }
+
+    #[test]
+    fn all_renders_the_all_value() {
+        let mut v = variable(true, true);
+        v.set_options(vec!["a".to_string()]);
+        assert!(v.all);
+        assert_eq!(v.value(), DEFAULT_ALL_VALUE);
+        v.toggle("a");
+        assert_eq!(v.value(), "(a)");
+    }
+
+    #[test]
+    fn multi_select_never_ends_up_empty() {
+        let mut with_all = variable(true, true);
+        with_all.set_options(vec!["a".to_string(), "b".to_string()]);
+        with_all.toggle(ALL_OPTION);
+        assert!(!with_all.all);
+        assert_eq!(with_all.value(), "(a)");
+        with_all.toggle("a");
+        assert!(with_all.all);
+        assert_eq!(with_all.value(), DEFAULT_ALL_VALUE);
+
+        let mut without_all = variable(true, false);
+        without_all.set_options(vec!["a".to_string(), "b".to_string()]);
+        without_all.toggle("a");
+        assert_eq!(without_all.value(), "(a)");
+
+        let mut no_options = variable(true, false);
+        no_options.set_options(vec![]);
+        assert_eq!(no_options.value(), DEFAULT_ALL_VALUE);
+    }
+
+    #[test]
+    fn defaults_must_name_an_option() {
+        let mut v = variable(false, true);
+        v.def.default = Some("b".to_string());
+        v.set_options(vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(v.value(), "b");
+
+        let mut v = variable(false, true);
+        v.def.default = Some("a.*".to_string());
+        v.set_options(vec!["a".to_string(), "b".to_string()]);
+        assert!(v.all);
+    }
+
+    #[test]
+    fn label_values_queries() {
+        assert_eq!(parse_label_values("label_values(pod)"), Ok((None, "pod".to_string())));
+        assert_eq!(parse_label_values(" label_values(up{job=\"a\"}, pod) "),
+                   Ok((Some("up{job=\"a\"}".to_string()), "pod".to_string())));
+        assert!(parse_label_values("pod").is_err());
+    }
+}